@@ -0,0 +1,75 @@
+//! times writing a tar.gz and a zip archive with their checksum files from one `CargoPack`
+//! without and with the content cache, run with `cargo bench`. With the cache, each source file
+//! is read once for both archives.
+
+extern crate cargo;
+extern crate cargo_pack;
+
+use cargo::util::{paths, Config};
+use cargo_pack::{ArchiveFormat, CargoPack};
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+const FILES: usize = 200;
+const FILE_SIZE: usize = 64 * 1024;
+const CACHE_LIMIT: u64 = 64 * 1024 * 1024;
+const RUNS: u32 = 5;
+
+fn write(path: &Path, content: &[u8]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path).and_then(|mut f| f.write_all(content)).unwrap();
+}
+
+/// writes a library package, so with no binaries to build, packing `FILES` files of `FILE_SIZE` bytes
+fn package(dir: &Path) {
+    write(
+        &dir.join("Cargo.toml"),
+        b"[package]\nname = \"bench\"\nversion = \"0.1.0\"\nauthors = []\n\n\
+          [package.metadata.pack]\nfiles = [\"assets/**/*.bin\"]\n",
+    );
+    write(&dir.join("src/lib.rs"), b"");
+    for i in 0..FILES {
+        let content = (0..FILE_SIZE).map(|j| ((i * 31 + j * 7) % 251) as u8).collect::<Vec<_>>();
+        write(&dir.join(format!("assets/{}/{}.bin", i % 10, i)), &content);
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos()) / 1_000_000
+}
+
+fn main() {
+    let dir = env::temp_dir().join(format!("cargo-pack-bench-cache-{}", process::id()));
+    package(&dir);
+    let config = Config::default().unwrap();
+    let mut digests = Vec::new();
+    for &limit in &[0, CACHE_LIMIT] {
+        let pack = CargoPack::builder(&config)
+            .with_manifest_path(dir.join("Cargo.toml"))
+            .with_content_cache(limit)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            for &(format, name) in &[(ArchiveFormat::TarGz, "bench.tar.gz"), (ArchiveFormat::Zip, "bench.zip")] {
+                let (_, checksum) = pack.create_archive_with_checksum(&dir.join("out").join(name), format)
+                    .unwrap();
+                digests.push(paths::read_bytes(&checksum).unwrap());
+            }
+        }
+        println!(
+            "tar.gz and zip of {} files of {} bytes with a cache of {} bytes: {} ms per run",
+            FILES,
+            FILE_SIZE,
+            limit,
+            millis(start.elapsed() / RUNS)
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    let half = digests.len() / 2;
+    assert!(digests[..half] == digests[half..], "the cache changed the archives");
+}
@@ -0,0 +1,81 @@
+//! times resolving many glob patterns with one thread and with several, run with `cargo bench`.
+//! The number of threads is the first argument, 4 by default. Threads only help with as many cores.
+
+extern crate cargo;
+extern crate cargo_pack;
+
+use cargo::util::Config;
+use cargo_pack::CargoPack;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+const PATTERNS: usize = 64;
+const FILES: usize = 200;
+const RUNS: u32 = 5;
+
+fn write(path: &Path, content: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path)
+        .and_then(|mut f| f.write_all(content.as_bytes()))
+        .unwrap();
+}
+
+/// writes a package whose `files` has a pattern for each of `PATTERNS` directories of `FILES` files
+fn package(dir: &Path) {
+    let files = (0..PATTERNS)
+        .map(|i| format!("\"assets/{}/**/*.txt\"", i))
+        .collect::<Vec<_>>();
+    write(
+        &dir.join("Cargo.toml"),
+        &format!(
+            "[package]\nname = \"bench\"\nversion = \"0.1.0\"\nauthors = []\n\n\
+             [package.metadata.pack]\nfiles = [{}]\n",
+            files.join(", ")
+        ),
+    );
+    write(&dir.join("src/main.rs"), "fn main() {}\n");
+    for i in 0..PATTERNS {
+        for j in 0..FILES {
+            write(&dir.join(format!("assets/{}/{}/{}.txt", i, j % 10, j)), "asset");
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_nanos()) / 1_000_000
+}
+
+fn main() {
+    let jobs = env::args()
+        .nth(1)
+        .and_then(|jobs| jobs.parse().ok())
+        .unwrap_or(4);
+    let dir = env::temp_dir().join(format!("cargo-pack-bench-{}", process::id()));
+    package(&dir);
+    let config = Config::default().unwrap();
+    let mut entries = Vec::new();
+    for &jobs in &[1, jobs] {
+        let pack = CargoPack::builder(&config)
+            .with_manifest_path(dir.join("Cargo.toml"))
+            .with_jobs(jobs)
+            .build()
+            .unwrap();
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            entries.push(pack.entries().unwrap());
+        }
+        println!(
+            "{} patterns of {} files in {} threads: {} ms per run",
+            PATTERNS,
+            FILES,
+            jobs,
+            millis(start.elapsed() / RUNS)
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(entries.iter().all(|e| *e == entries[0]), "threads resolved other files");
+}
@@ -0,0 +1,1449 @@
+//! archive writers built on top of the resolved file set
+
+use {CargoPack, EntrySource, FileEntry, PackEntry, ensure_dir, ensure_parent_dir, subtract_negated};
+use brace;
+use cache::ContentCache;
+use copy::set_mode;
+use plan::PackPlan;
+use cargo::util::Sha256;
+use filetime::{self, FileTime};
+use error::*;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use glob;
+use serde::de::{self, Deserialize, Deserializer};
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tar::{Archive, Builder, EntryType, Header};
+use time;
+#[cfg(unix)]
+use xattr;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+use zip::{CompressionMethod, ZipWriter};
+use zip::write::FileOptions;
+use zstd;
+
+/// archive formats a packer can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArchiveFormat {
+    /// gzip compressed tarball, `tar.gz`
+    TarGz,
+    /// zstd compressed tarball, `tar.zst`
+    TarZst,
+    /// xz compressed tarball, `tar.xz`
+    TarXz,
+    /// zip archive, `zip`
+    Zip,
+}
+
+const FORMATS: &[(&str, ArchiveFormat)] = &[
+    ("tar.gz", ArchiveFormat::TarGz),
+    ("tar.zst", ArchiveFormat::TarZst),
+    ("tar.xz", ArchiveFormat::TarXz),
+    ("zip", ArchiveFormat::Zip),
+];
+
+impl ArchiveFormat {
+    /// returns the name of the format, which is also the file extension.
+    pub fn name(&self) -> &'static str {
+        FORMATS.iter().find(|&&(_, f)| f == *self).unwrap().0
+    }
+
+    /// guesses the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<ArchiveFormat> {
+        let name = path.file_name()?.to_string_lossy();
+        FORMATS
+            .iter()
+            .find(|&&(ext, _)| name.ends_with(&format!(".{}", ext)))
+            .map(|&(_, f)| f)
+    }
+}
+
+impl fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match FORMATS.iter().find(|&&(name, _)| name == s) {
+            Some(&(_, format)) => Ok(format),
+            None => {
+                let names = FORMATS.iter().map(|&(name, _)| name).collect::<Vec<_>>();
+                Err(format!("unknown archive format `{}`, expected one of {}", s, names.join(", ")).into())
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ArchiveFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// what an archive method wrote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveSummary {
+    /// the archive file. Empty when written to a writer
+    pub path: PathBuf,
+    /// number of files in the archive
+    pub entry_count: usize,
+    /// total size in bytes of the files
+    pub uncompressed_bytes: u64,
+    /// size in bytes of the archive file
+    pub compressed_bytes: u64,
+    /// the checksum file written next to the archive, if any
+    pub checksum: Option<PathBuf>,
+}
+
+/// a writer which computes the sha256 digest of the bytes written through it
+struct DigestWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> DigestWriter<W> {
+    fn new(inner: W) -> Self {
+        DigestWriter {
+            inner: inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn finish(mut self) -> Result<String> {
+        self.inner.flush()?;
+        Ok(to_hex(&self.hasher.finish()))
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(unix)]
+pub(crate) fn file_mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_mode(_meta: &fs::Metadata) -> u32 {
+    0o644
+}
+
+/// modification time of archive files, given by `CargoPackBuilder::with_output_mtime`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMtime {
+    /// leaves the time the archive is written at
+    Keep,
+    /// sets the time
+    At(SystemTime),
+    /// sets the latest modification time of the files packed. Generated files are not counted
+    LatestSource,
+}
+
+/// how the archive writers read source files
+pub(crate) struct Sources<'a> {
+    pub(crate) cache: &'a ContentCache,
+    /// records the extended attributes of source files in tarballs
+    pub(crate) xattrs: bool,
+}
+
+/// returns a pax extended header record, `<length> <key>=<value>\n`
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    // the length counts the whole record including its own digits
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// returns the extended attributes of `path` as pax records, sorted by the name
+#[cfg(unix)]
+fn xattr_records(path: &Path) -> Result<Vec<u8>> {
+    let mut names = match xattr::list(path) {
+        Ok(names) => names.collect::<Vec<_>>(),
+        Err(e) => {
+            warn!("cannot read extended attributes of {}: {}", path.display(), e);
+            return Ok(Vec::new());
+        }
+    };
+    names.sort();
+    let mut records = Vec::new();
+    for name in names {
+        if let Some(value) = xattr::get(path, &name)? {
+            records.extend(pax_record(&format!("SCHILY.xattr.{}", name.to_string_lossy()), &value));
+        }
+    }
+    Ok(records)
+}
+
+#[cfg(not(unix))]
+fn xattr_records(path: &Path) -> Result<Vec<u8>> {
+    warn!("extended attributes of {} are not packed on this platform", path.display());
+    Ok(Vec::new())
+}
+
+/// returns a header of a file of `size` bytes with `mode` whose owner and mtime are zeroed,
+/// so that the same input always produces the same bytes
+fn normalized_header(size: u64, mode: u32) -> Header {
+    let mut header = Header::new_gnu();
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_mtime(0);
+    header.set_size(size);
+    header.set_mode(mode);
+    header
+}
+
+/// appends `entry` as `dest` with a normalized header
+fn append_normalized<W: Write>(
+    builder: &mut Builder<W>,
+    entry: &PackEntry,
+    dest: &Path,
+    sources: &Sources,
+) -> Result<()> {
+    match entry.source {
+        EntrySource::Path(ref src) => {
+            let meta = fs::metadata(src)?;
+            let mut header = normalized_header(meta.len(), entry.mode.unwrap_or_else(|| file_mode(&meta)));
+            if sources.xattrs {
+                let records = xattr_records(src)?;
+                if !records.is_empty() {
+                    let mut pax = Header::new_ustar();
+                    pax.set_entry_type(EntryType::XHeader);
+                    pax.set_uid(0);
+                    pax.set_gid(0);
+                    pax.set_mtime(0);
+                    pax.set_mode(0o644);
+                    pax.set_size(records.len() as u64);
+                    let name = dest.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                    builder.append_data(&mut pax, Path::new("PaxHeaders").join(name), records.as_slice())?;
+                }
+            }
+            match sources.cache.get(src)? {
+                Some(content) => builder.append_data(&mut header, dest, content.as_slice())?,
+                None => builder.append_data(&mut header, dest, File::open(src)?)?,
+            }
+        }
+        EntrySource::Content(ref content) => {
+            let mut header = normalized_header(content.len() as u64, entry.mode.unwrap_or(0o644));
+            builder.append_data(&mut header, dest, content.as_slice())?;
+        }
+    }
+    Ok(())
+}
+
+/// a writer which counts the bytes written through it
+struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// appends `entries` at their destinations
+fn append_all<W: Write>(builder: &mut Builder<W>, entries: &[PackEntry], sources: &Sources) -> Result<()> {
+    for entry in entries {
+        append_normalized(builder, entry, &entry.dest, sources)?;
+    }
+    Ok(())
+}
+
+/// something appending entries to a tarball
+type Fill<'a> = &'a mut FnMut(&mut Builder<&mut Write>) -> Result<()>;
+
+/// writes a tarball filled by `fill` into `writer` and returns the writer back
+fn tar_into<W: Write>(mut writer: W, fill: Fill) -> Result<W> {
+    {
+        let mut builder = Builder::new(&mut writer as &mut Write);
+        fill(&mut builder)?;
+        builder.finish()?;
+    }
+    Ok(writer)
+}
+
+/// valid compression levels of the tarball formats: the lowest, the highest and the default
+const LEVELS: &[(ArchiveFormat, i32, i32, i32)] = &[
+    (ArchiveFormat::TarGz, 0, 9, 6),
+    (ArchiveFormat::TarZst, 1, 21, 3),
+    (ArchiveFormat::TarXz, 0, 9, 6),
+];
+
+/// returns `level`, or the default if not given, clamped to the range of `format`
+fn compression_level(format: ArchiveFormat, level: Option<i32>) -> i32 {
+    let (min, max, default) = match LEVELS.iter().find(|&&(f, _, _, _)| f == format) {
+        Some(&(_, min, max, default)) => (min, max, default),
+        None => return 0,
+    };
+    match level {
+        None => default,
+        Some(level) if level < min || level > max => {
+            let clamped = if level < min { min } else { max };
+            warn!(
+                "compression level {} is out of {} to {} of {}. using {}",
+                level, min, max, format, clamped
+            );
+            clamped
+        }
+        Some(level) => level,
+    }
+}
+
+/// a writer compressing into `W` in one of the tarball formats
+enum Compressor<W: Write> {
+    Gz(GzEncoder<W>),
+    Zst(zstd::stream::write::Encoder<W>),
+    Xz(XzEncoder<W>),
+}
+
+impl<W: Write> Compressor<W> {
+    /// starts compressing into `writer` in `format` at `level`, or the default if not given
+    fn new(writer: W, format: ArchiveFormat, level: Option<i32>) -> Result<Self> {
+        let level = compression_level(format, level);
+        Ok(match format {
+            ArchiveFormat::TarGz => Compressor::Gz(GzEncoder::new(writer, Compression::new(level as u32))),
+            ArchiveFormat::TarZst => Compressor::Zst(zstd::stream::write::Encoder::new(writer, level)?),
+            ArchiveFormat::TarXz => Compressor::Xz(XzEncoder::new(writer, level as u32)),
+            ArchiveFormat::Zip => return Err("zip is not a tarball format and needs a file to write to".into()),
+        })
+    }
+
+    /// writes the end of the compressed stream and returns the writer back
+    fn finish(self) -> Result<W> {
+        Ok(match self {
+            Compressor::Gz(w) => w.finish()?,
+            Compressor::Zst(w) => w.finish()?,
+            Compressor::Xz(w) => w.finish()?,
+        })
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Compressor::Gz(ref mut w) => w.write(buf),
+            Compressor::Zst(ref mut w) => w.write(buf),
+            Compressor::Xz(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Compressor::Gz(ref mut w) => w.flush(),
+            Compressor::Zst(ref mut w) => w.flush(),
+            Compressor::Xz(ref mut w) => w.flush(),
+        }
+    }
+}
+
+/// writes a tarball filled by `fill` compressed in `format` at `level` into `writer`, and
+/// returns the number of bytes written
+fn compressed_tar_into<W: Write>(writer: W, format: ArchiveFormat, level: Option<i32>, fill: Fill) -> Result<u64> {
+    let mut writer = CountingWriter {
+        inner: writer,
+        count: 0,
+    };
+    tar_into(Compressor::new(&mut writer, format, level)?, fill)?.finish()?;
+    writer.flush()?;
+    Ok(writer.count)
+}
+
+/// returns `content` gzipped at the best level. The header has no name and no mtime, so the
+/// output only depends on the content.
+pub(crate) fn gzip(content: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(content)?;
+    Ok(encoder.finish()?)
+}
+
+/// opens the tarball `file` of `format` to read it uncompressed
+fn tar_reader(file: File, format: ArchiveFormat) -> Result<Box<Read>> {
+    Ok(match format {
+        ArchiveFormat::TarGz => Box::new(GzDecoder::new(file)),
+        ArchiveFormat::TarZst => Box::new(zstd::stream::read::Decoder::new(file)?),
+        ArchiveFormat::TarXz => Box::new(XzDecoder::new(file)),
+        ArchiveFormat::Zip => return Err("cannot read zip archives as tarballs".into()),
+    })
+}
+
+/// fails if `out` is the source of any of `entries`. This happens when the output is written into
+/// a directory matched by `files`, and the previous output would be packed into the new one.
+fn check_not_packed(out: &Path, entries: &[PackEntry]) -> Result<()> {
+    let out = match (out.parent(), out.file_name()) {
+        (Some(dir), Some(name)) if dir.as_os_str().is_empty() => Path::new(".").canonicalize()?.join(name),
+        (Some(dir), Some(name)) => dir.canonicalize()?.join(name),
+        _ => return Ok(()),
+    };
+    for entry in entries {
+        if let EntrySource::Path(ref src) = entry.source {
+            if src.canonicalize().ok().as_ref() == Some(&out) {
+                return Err(format!(
+                    "the output {} is matched by files as {}. write it outside of the packed files",
+                    out.display(),
+                    entry.dest.display()
+                ).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// returns `path`, absolute, with its longest existing ancestor resolved, so that it compares
+/// with resolved paths before it is written
+fn resolve_existing(path: &Path) -> Result<PathBuf> {
+    let path = env::current_dir()?.join(path);
+    let mut names = Vec::new();
+    let mut dir = path.as_path();
+    loop {
+        if let Ok(real) = dir.canonicalize() {
+            return Ok(names.iter().rev().fold(real, |real, name| real.join(name)));
+        }
+        match (dir.parent(), dir.file_name()) {
+            (Some(parent), Some(name)) => {
+                names.push(name);
+                dir = parent;
+            }
+            _ => return Ok(path.clone()),
+        }
+    }
+}
+
+/// returns the total size in bytes of the contents of `entries`
+fn content_bytes(entries: &[PackEntry]) -> Result<u64> {
+    let mut total = 0;
+    for entry in entries {
+        total += match entry.source {
+            EntrySource::Path(ref src) => fs::metadata(src)?.len(),
+            EntrySource::Content(ref content) => content.len() as u64,
+        };
+    }
+    Ok(total)
+}
+
+/// a file written in place of another, removed when dropped unless it replaced the other
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    /// returns the temporary file of `path`, `<path>.tmp`. It is not created.
+    fn of(path: &Path) -> TempFile {
+        let mut tmp = path.as_os_str().to_os_string();
+        tmp.push(".tmp");
+        TempFile {
+            path: PathBuf::from(tmp),
+            persisted: false,
+        }
+    }
+
+    /// moves the file to `path`
+    fn persist(mut self, path: &Path) -> Result<()> {
+        fs::rename(&self.path, path).chain_err(|| format!("failed to replace {}", path.display()))?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// size of a tar block, the unit of headers and of padded contents
+const TAR_BLOCK: u64 = 512;
+
+/// returns `size` rounded up to whole tar blocks
+fn tar_blocks(size: u64) -> u64 {
+    (size + TAR_BLOCK - 1) / TAR_BLOCK * TAR_BLOCK
+}
+
+/// returns the bytes an entry named `name` of `size` bytes takes in an uncompressed archive of
+/// `format`
+fn entry_overhead(format: ArchiveFormat, name: &str, size: u64) -> u64 {
+    let name_len = name.len() as u64;
+    match format {
+        ArchiveFormat::Zip => {
+            // local file header and central directory header, each followed by the name
+            30 + 46 + 2 * name_len + size
+        }
+        _ => {
+            // names longer than 100 bytes take a GNU long name entry of their own
+            let long_name = if name_len > 100 { TAR_BLOCK + tar_blocks(name_len + 1) } else { 0 };
+            long_name + TAR_BLOCK + tar_blocks(size)
+        }
+    }
+}
+
+/// writes the checksum file `<out>.sha256` of the archive `out` and returns its path
+fn write_checksum(out: &Path) -> Result<PathBuf> {
+    let name = out.file_name()
+        .ok_or_else(|| Error::from(format!("{} is not a file path", out.display())))?;
+    let digest = file_digest(out)?;
+    let sidecar = checksum_path(out);
+    File::create(&sidecar)
+        .and_then(|mut f| writeln!(f, "{}  {}", digest, name.to_string_lossy()))
+        .chain_err(|| format!("failed to write {}", sidecar.display()))?;
+    Ok(sidecar)
+}
+
+/// sets the modification time of the archive `out`
+pub(crate) fn set_output_mtime(out: &Path, mtime: FileTime) -> Result<()> {
+    filetime::set_file_times(out, mtime, mtime).chain_err(|| format!("failed to set the mtime of {}", out.display()))
+}
+
+/// extensions of files stored without compression in zip archives when `store-extensions` is not
+/// set. They are compressed already
+const STORE_EXTENSIONS: &[&str] = &[
+    "7z", "avif", "br", "bz2", "gif", "gz", "jpeg", "jpg", "mkv", "mov", "mp3", "mp4", "ogg", "png",
+    "webm", "webp", "woff2", "xz", "zip", "zst",
+];
+
+/// how archives are compressed, from the config
+#[derive(Debug, Clone)]
+pub(crate) struct CompressOptions {
+    /// compression level of tarballs
+    pub(crate) level: Option<i32>,
+    /// extensions of files stored in zip archives, lowercased
+    pub(crate) store_extensions: Vec<String>,
+}
+
+/// writes `entries` into `out` as an archive of `format`. The archive is written into `<out>.tmp`
+/// and moved to `out` once finished, so `out` is left as it was on errors.
+pub(crate) fn write_archive(
+    out: &Path,
+    format: ArchiveFormat,
+    compress: &CompressOptions,
+    entries: &[PackEntry],
+    sources: &Sources,
+) -> Result<ArchiveSummary> {
+    ensure_parent_dir(out)?;
+    check_not_packed(out, entries)?;
+    let tmp = TempFile::of(out);
+    let file = File::create(&tmp.path).chain_err(|| format!("failed to create {}", tmp.path.display()))?;
+    let summary = match format {
+        ArchiveFormat::Zip => {
+            let mut zip = ZipArchiveWriter::new(file, out, compress.store_extensions.clone());
+            for entry in entries {
+                feed(&mut zip, entry, sources)?;
+            }
+            zip.finish()?
+        }
+        _ => {
+            let compressed_bytes = compressed_tar_into(file, format, compress.level, &mut |builder| {
+                append_all(builder, entries, sources)
+            })?;
+            ArchiveSummary {
+                path: out.to_path_buf(),
+                entry_count: entries.len(),
+                uncompressed_bytes: content_bytes(entries)?,
+                compressed_bytes: compressed_bytes,
+                checksum: None,
+            }
+        }
+    };
+    tmp.persist(out)?;
+    debug!("wrote {} archive: {:?}", format, summary);
+    Ok(summary)
+}
+
+/// a backend writing archives for `CargoPack::pack_into`.
+///
+/// `pack_into` calls `add_entry` once for each file, in the order of the destinations with no
+/// destination repeated, and then `finish`. Directories are not added: a backend creates them
+/// as needed from the destinations. `reader` yields exactly `size` bytes, and `mode` is the
+/// permission bits of the file. Owners and timestamps are left to the backend; the built-in
+/// ones zero them so that archives are reproducible.
+///
+/// `TarWriter` and `ZipArchiveWriter` are the writers used by `create_archive`.
+pub trait ArchiveWriter {
+    /// adds a file at `dest` whose content is read from `reader`
+    fn add_entry(&mut self, dest: &Path, reader: &mut Read, size: u64, mode: u32) -> Result<()>;
+    /// ends the archive and returns what was written
+    fn finish(self) -> Result<ArchiveSummary>;
+}
+
+/// an `ArchiveWriter` writing a compressed tarball into `W`, as `write_tar` does. Extended
+/// attributes are not recorded.
+pub struct TarWriter<W: Write> {
+    builder: Builder<Compressor<CountingWriter<W>>>,
+    entry_count: usize,
+    uncompressed_bytes: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// starts a tarball of `format` compressed at `level`, the default of the format if not
+    /// given. `format` must be one of the tarball formats.
+    pub fn new(writer: W, format: ArchiveFormat, level: Option<i32>) -> Result<Self> {
+        let writer = CountingWriter {
+            inner: writer,
+            count: 0,
+        };
+        Ok(TarWriter {
+            builder: Builder::new(Compressor::new(writer, format, level)?),
+            entry_count: 0,
+            uncompressed_bytes: 0,
+        })
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn add_entry(&mut self, dest: &Path, reader: &mut Read, size: u64, mode: u32) -> Result<()> {
+        self.builder.append_data(&mut normalized_header(size, mode), dest, reader)?;
+        self.entry_count += 1;
+        self.uncompressed_bytes += size;
+        Ok(())
+    }
+
+    /// the `path` of the summary is empty
+    fn finish(self) -> Result<ArchiveSummary> {
+        let mut writer = self.builder.into_inner()?.finish()?;
+        writer.flush()?;
+        Ok(ArchiveSummary {
+            path: PathBuf::new(),
+            entry_count: self.entry_count,
+            uncompressed_bytes: self.uncompressed_bytes,
+            compressed_bytes: writer.count,
+            checksum: None,
+        })
+    }
+}
+
+/// an `ArchiveWriter` writing a zip archive. Like the tarballs, the timestamps are fixed, to
+/// 1980-01-01 which is the earliest time zip can represent. Files with one of the store
+/// extensions are stored as they are and others are deflated.
+pub struct ZipArchiveWriter {
+    zip: ZipWriter<File>,
+    path: PathBuf,
+    store_extensions: Vec<String>,
+    entry_count: usize,
+    uncompressed_bytes: u64,
+}
+
+impl ZipArchiveWriter {
+    /// creates the zip archive `out`, storing files with the default store extensions
+    pub fn create(out: &Path) -> Result<Self> {
+        ensure_parent_dir(out)?;
+        let file = File::create(out).chain_err(|| format!("failed to create {}", out.display()))?;
+        Ok(Self::new(
+            file,
+            out,
+            STORE_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+        ))
+    }
+
+    /// writes into `file` at `path` storing files with `store_extensions`, lowercased
+    fn new(file: File, path: &Path, store_extensions: Vec<String>) -> Self {
+        ZipArchiveWriter {
+            zip: ZipWriter::new(file),
+            path: path.to_path_buf(),
+            store_extensions: store_extensions,
+            entry_count: 0,
+            uncompressed_bytes: 0,
+        }
+    }
+}
+
+impl ArchiveWriter for ZipArchiveWriter {
+    fn add_entry(&mut self, dest: &Path, reader: &mut Read, size: u64, mode: u32) -> Result<()> {
+        let extension = dest.extension().map(|e| e.to_string_lossy().to_lowercase());
+        let method = match extension {
+            Some(ref e) if self.store_extensions.contains(e) => CompressionMethod::Stored,
+            _ => CompressionMethod::Deflated,
+        };
+        let options = FileOptions::default()
+            .compression_method(method)
+            .last_modified_time(time::at_utc(time::Timespec::new(315_532_800, 0)))
+            .unix_permissions(mode);
+        self.zip.start_file(dest.to_string_lossy().replace('\\', "/"), options)?;
+        io::copy(reader, &mut self.zip)?;
+        self.entry_count += 1;
+        self.uncompressed_bytes += size;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<ArchiveSummary> {
+        let file = self.zip.finish()?;
+        Ok(ArchiveSummary {
+            path: self.path,
+            entry_count: self.entry_count,
+            uncompressed_bytes: self.uncompressed_bytes,
+            compressed_bytes: file.metadata()?.len(),
+            checksum: None,
+        })
+    }
+}
+
+/// adds `entry` to `writer`, reading the source through the cache
+fn feed<A: ArchiveWriter>(writer: &mut A, entry: &PackEntry, sources: &Sources) -> Result<()> {
+    match entry.source {
+        EntrySource::Path(ref src) => {
+            let meta = fs::metadata(src)?;
+            let mode = entry.mode.unwrap_or_else(|| file_mode(&meta));
+            match sources.cache.get(src)? {
+                Some(content) => writer.add_entry(&entry.dest, &mut content.as_slice(), content.len() as u64, mode),
+                None => {
+                    let mut file = File::open(src).chain_err(|| format!("failed to open {}", src.display()))?;
+                    writer.add_entry(&entry.dest, &mut file, meta.len(), mode)
+                }
+            }
+        }
+        EntrySource::Content(ref content) => writer.add_entry(
+            &entry.dest,
+            &mut content.as_slice(),
+            content.len() as u64,
+            entry.mode.unwrap_or(0o644),
+        ),
+    }.chain_err(|| format!("failed to add {}", entry.dest.display()))
+}
+
+/// returns the checksum file of the archive `out`, `<out>.sha256`
+fn checksum_path(out: &Path) -> PathBuf {
+    let mut sidecar = out.as_os_str().to_os_string();
+    sidecar.push(".sha256");
+    PathBuf::from(sidecar)
+}
+
+/// computes the sha256 digest of the file at `path`
+fn file_digest(path: &Path) -> Result<String> {
+    let mut digest = DigestWriter::new(io::sink());
+    io::copy(&mut File::open(path)?, &mut digest)?;
+    digest.finish()
+}
+
+/// computes the sha256 digest of the content of `entry`
+pub(crate) fn entry_digest(entry: &PackEntry) -> Result<String> {
+    match entry.source {
+        EntrySource::Path(ref src) => file_digest(src),
+        EntrySource::Content(ref content) => {
+            let mut digest = DigestWriter::new(io::sink());
+            digest.write_all(content)?;
+            digest.finish()
+        }
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// fails if `out`, a file about to be written, would be packed by `files` once it exists.
+    /// Otherwise the next run would pack what this one wrote.
+    pub(crate) fn check_output(&self, out: &Path) -> Result<()> {
+        match self.packing_entry(out)? {
+            Some(entry) => Err(format!(
+                "the output {} would be matched by files entry `{}`. write it outside of the packed files",
+                out.display(),
+                entry.pattern()
+            ).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// returns the entry of `files` which would pack `path` if it existed, as `resolve` expands
+    /// the entries: matched by the entry and not removed by the negated patterns after it or by
+    /// `exclude-extensions`. Entries only packing files tracked by git with `git-tracked-only`
+    /// are skipped as the file is not tracked.
+    fn packing_entry(&self, path: &Path) -> Result<Option<&FileEntry>> {
+        let package = self.package()?;
+        let path = resolve_existing(path)?;
+        let options = self.match_options();
+        let literal_separator = glob::MatchOptions {
+            require_literal_separator: true,
+            ..options.clone()
+        };
+        let matches = |pattern: &str, rel: &Path| -> Result<bool> {
+            let pattern = if pattern.starts_with("\\!") { &pattern[1..] } else { pattern };
+            for alternative in brace::expand(pattern)? {
+                if glob::Pattern::new(&alternative)?.matches_path_with(rel, &literal_separator) {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+        let git_tracked_only = self.pack_config.git_tracked_only.unwrap_or(false);
+        let extensions = self.pack_config
+            .exclude_extensions
+            .iter()
+            .flat_map(|e| e)
+            .map(|e| e.trim_left_matches('.').to_lowercase())
+            .collect::<Vec<_>>();
+        if path.extension().map_or(false, |e| extensions.contains(&e.to_string_lossy().to_lowercase())) {
+            return Ok(None);
+        }
+        let files = self.files();
+        for (index, entry) in files.iter().enumerate() {
+            if entry.negated().is_some() || (git_tracked_only && entry.is_glob()) {
+                continue;
+            }
+            let root = resolve_existing(&self.entry_root(package, entry)?)?;
+            let rel = match path.strip_prefix(&root) {
+                Ok(rel) => rel.to_path_buf(),
+                Err(_) => continue,
+            };
+            let matched = match *entry {
+                FileEntry::Pattern(ref pattern) | FileEntry::Based { glob: ref pattern, .. } => matches(pattern, &rel)?,
+                FileEntry::MemberFiles { ref files, .. } => {
+                    let mut matched = false;
+                    for pattern in files {
+                        matched = matched || matches(pattern, &rel)?;
+                    }
+                    matched
+                }
+                FileEntry::Rename { ref from, .. } => path == resolve_existing(&package.root().join(from))?,
+                FileEntry::Dir { ref dir, .. } => path.starts_with(resolve_existing(&package.root().join(dir))?),
+                FileEntry::WorkspacePath { ref from, .. } => {
+                    path.starts_with(resolve_existing(&package.root().join(from))?)
+                }
+                FileEntry::MemberArtifact { .. } | FileEntry::Conditional { .. } => false,
+            };
+            if !matched {
+                continue;
+            }
+            let mut kept = vec![
+                PackEntry {
+                    source: EntrySource::Path(path.clone()),
+                    dest: rel,
+                    mode: None,
+                },
+            ];
+            subtract_negated(files, index, &root, &options, &mut kept)?;
+            if !kept.is_empty() {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+
+    fn sources(&self) -> Sources {
+        Sources {
+            cache: &self.content_cache,
+            xattrs: self.preserve_xattrs,
+        }
+    }
+
+    /// returns how to compress archives as configured
+    pub(crate) fn compress_options(&self) -> CompressOptions {
+        let store_extensions = match self.pack_config.store_extensions {
+            Some(ref extensions) => extensions
+                .iter()
+                .map(|e| e.trim_left_matches('.').to_lowercase())
+                .collect(),
+            None => STORE_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+        };
+        CompressOptions {
+            level: self.pack_config.compression_level,
+            store_extensions: store_extensions,
+        }
+    }
+
+    /// returns the modification time to set to the archive of `entries`, if any
+    pub(crate) fn output_mtime(&self, entries: &[PackEntry]) -> Result<Option<FileTime>> {
+        match self.output_mtime {
+            OutputMtime::Keep => Ok(None),
+            OutputMtime::At(time) => {
+                let since_epoch = time.duration_since(UNIX_EPOCH)
+                    .chain_err(|| "the mtime of the archive must be after 1970")?;
+                Ok(Some(FileTime::from_seconds_since_1970(since_epoch.as_secs(), since_epoch.subsec_nanos())))
+            }
+            OutputMtime::LatestSource => {
+                let mut latest = None;
+                for entry in entries {
+                    if let EntrySource::Path(ref src) = entry.source {
+                        let mtime = FileTime::from_last_modification_time(&fs::metadata(src)?);
+                        if latest.map_or(true, |latest| mtime > latest) {
+                            latest = Some(mtime);
+                        }
+                    }
+                }
+                Ok(latest)
+            }
+        }
+    }
+
+    /// sets the modification time of the archive `out` of `entries` as configured
+    fn touch_output(&self, out: &Path, entries: &[PackEntry]) -> Result<()> {
+        if let Some(mtime) = self.output_mtime(entries)? {
+            set_output_mtime(out, mtime)?;
+        }
+        Ok(())
+    }
+
+    /// returns what archives contain: the binaries followed by the entries, sorted by destination.
+    pub(crate) fn archive_entries(&self) -> Result<Vec<PackEntry>> {
+        Ok(self.plan()?.entries.into_iter().map(|e| e.entry).collect())
+    }
+
+    /// writes the binaries and the entries into `out` as an archive of `format`.
+    ///
+    /// Entries are sorted and their metadata is normalized as `create_oci_layer` does.
+    /// It is an error if `out` is one of the files to pack, or would be matched by `files` once
+    /// written, which is checked before writing anything. An existing `out` is only replaced once
+    /// the new archive is written.
+    pub fn create_archive(&self, out: &Path, format: ArchiveFormat) -> Result<ArchiveSummary> {
+        let _lock = self.lock()?;
+        self.check_output(out)?;
+        let entries = self.archive_entries()?;
+        let summary = write_archive(out, format, &self.compress_options(), &entries, &self.sources())?;
+        self.touch_output(out, &entries)?;
+        Ok(summary)
+    }
+
+    /// feeds the binaries and the entries into `writer`, a backend of any archive format, and
+    /// finishes it. The entries are the same as `create_archive` packs, in the order of the
+    /// destinations. The output mtime of the builder is not applied as the output is not known.
+    pub fn pack_into<A: ArchiveWriter>(&self, mut writer: A) -> Result<ArchiveSummary> {
+        let _lock = self.lock()?;
+        let entries = self.archive_entries()?;
+        let sources = self.sources();
+        for entry in &entries {
+            feed(&mut writer, entry, &sources)?;
+        }
+        writer.finish()
+    }
+
+    /// writes the same tarball as `create_archive` into `writer`, e.g. `io::stdout()` to pipe it
+    /// into another command. `writer` is flushed at the end.
+    ///
+    /// `format` must be one of the tarball formats; zip archives need a file to seek.
+    /// The `path` of the returned summary is empty.
+    pub fn write_tar<W: Write>(&self, writer: W, format: ArchiveFormat) -> Result<ArchiveSummary> {
+        let _lock = self.lock()?;
+        let entries = self.archive_entries()?;
+        let sources = self.sources();
+        let compressed_bytes =
+            compressed_tar_into(writer, format, self.pack_config.compression_level, &mut |builder| {
+                append_all(builder, &entries, &sources)
+            })?;
+        let summary = ArchiveSummary {
+            path: PathBuf::new(),
+            entry_count: entries.len(),
+            uncompressed_bytes: content_bytes(&entries)?,
+            compressed_bytes: compressed_bytes,
+            checksum: None,
+        };
+        debug!("wrote {} archive: {:?}", format, summary);
+        Ok(summary)
+    }
+
+    /// appends the entries of `plan` into `builder`, a tarball owned by the caller, e.g. after
+    /// headers of its own. The builder is not finished, so more entries may follow.
+    ///
+    /// The entries are appended in the order of `plan`, sorted by the destination, each named by
+    /// its destination with the headers normalized as `create_archive` does. Nothing is appended
+    /// for the directories.
+    pub fn append_plan<W: Write>(&self, builder: &mut Builder<W>, plan: &PackPlan) -> Result<()> {
+        let _lock = self.lock()?;
+        let sources = self.sources();
+        for e in &plan.entries {
+            append_normalized(builder, &e.entry, &e.entry.dest, &sources)?;
+        }
+        Ok(())
+    }
+
+    /// estimates the size in bytes of the archive of `format` before compression, the contents of
+    /// the plan plus the headers of the format, without writing anything.
+    ///
+    /// Tarballs take a 512-byte header per entry, contents padded to 512-byte blocks and two zero
+    /// blocks at the end; zip archives take a local and a central header per entry and the end of
+    /// the central directory. The estimate is approximate: extended attributes and extra fields
+    /// are not counted.
+    pub fn estimated_archive_size(&self, format: ArchiveFormat) -> Result<u64> {
+        let plan = self.plan()?;
+        let entries = plan.entries
+            .iter()
+            .map(|e| entry_overhead(format, &e.entry.dest.to_string_lossy(), e.size))
+            .sum::<u64>();
+        let end = match format {
+            ArchiveFormat::Zip => 22,
+            _ => 2 * TAR_BLOCK,
+        };
+        Ok(entries + end)
+    }
+
+    /// writes the archive as `create_archive` does and a checksum file next to it.
+    ///
+    /// The checksum file is `<out>.sha256` and contains `<hex>  <file name>` in the format of
+    /// `sha256sum`, so it can be verified with `sha256sum -c`. The digest is computed from the
+    /// finished archive. Returns the paths of the archive and the checksum file.
+    pub fn create_archive_with_checksum(&self, out: &Path, format: ArchiveFormat) -> Result<(PathBuf, PathBuf)> {
+        out.file_name()
+            .ok_or_else(|| Error::from(format!("{} is not a file path", out.display())))?;
+        self.check_output(&checksum_path(out))?;
+        self.create_archive(out, format)?;
+        Ok((out.to_path_buf(), write_checksum(out)?))
+    }
+
+    /// writes what the config says in one call and returns the summaries of the archives written.
+    ///
+    /// The steps run in this order:
+    ///
+    /// 1. the package is resolved as `plan` does, including `BUILD-INFO` with `include-build-info`
+    ///    and the other generated files
+    /// 2. the archive of `format`, tar.gz by default, is written into `artifact-dir`, or
+    ///    `default_output_dir` without it, named as `archive_name` tells
+    /// 3. with `include-checksums`, the checksum file `<archive>.sha256` is written next to it and
+    ///    its path is the `checksum` of the summary
+    ///
+    /// The binaries must be built. Nothing is written if resolving fails.
+    pub fn finalize(&self) -> Result<Vec<ArchiveSummary>> {
+        let format = self.format().unwrap_or(ArchiveFormat::TarGz);
+        let dir = match self.pack_config.artifact_dir {
+            Some(ref dir) => {
+                let dir = self.package()?.root().join(dir);
+                ensure_dir(&dir)?;
+                dir
+            }
+            None => self.default_output_dir()?,
+        };
+        let out = dir.join(self.archive_name(format)?);
+        if self.pack_config.include_checksums.unwrap_or(false) {
+            self.check_output(&checksum_path(&out))?;
+        }
+        let mut summary = self.create_archive(&out, format)?;
+        if self.pack_config.include_checksums.unwrap_or(false) {
+            summary.checksum = Some(write_checksum(&out)?);
+        }
+        Ok(vec![summary])
+    }
+
+    /// appends files to the tarball `archive`. Each of `extra` is a pair of a source file and
+    /// its destination in the archive. The format is guessed from the extension of `archive`.
+    ///
+    /// Existing entries are kept as they are, including their headers, and the new files are
+    /// normalized as `create_archive` does. Compressed tarballs cannot be appended in place,
+    /// so the whole archive is decompressed and rewritten. It is an error if a destination is
+    /// already in the archive. The archive is left as it was on errors.
+    ///
+    /// With `OutputMtime::LatestSource`, the existing entries count as modified at the mtime the
+    /// archive had, as their sources are not known.
+    pub fn append_to_tar(&self, archive: &Path, extra: &[(PathBuf, PathBuf)]) -> Result<()> {
+        let format = ArchiveFormat::from_path(archive)
+            .ok_or_else(|| Error::from(format!("unknown archive format of {}", archive.display())))?;
+        let _lock = self.lock()?;
+        let open = || -> Result<Archive<Box<Read>>> {
+            let file = File::open(archive).chain_err(|| format!("failed to open {}", archive.display()))?;
+            Ok(Archive::new(tar_reader(file, format)?))
+        };
+        let mut existing = HashSet::new();
+        for entry in open()?.entries()? {
+            existing.insert(entry?.path()?.into_owned());
+        }
+        let extra = extra
+            .iter()
+            .map(|&(ref src, ref dest)| PackEntry {
+                source: EntrySource::Path(src.clone()),
+                dest: dest.clone(),
+                mode: None,
+            })
+            .collect::<Vec<_>>();
+        let mut dests = HashSet::new();
+        for entry in &extra {
+            if existing.contains(&entry.dest) || !dests.insert(&entry.dest) {
+                return Err(format!("{} is already in {}", entry.dest.display(), archive.display()).into());
+            }
+        }
+
+        let previous_mtime = FileTime::from_last_modification_time(&fs::metadata(archive)?);
+        let mtime = match self.output_mtime {
+            OutputMtime::LatestSource => Some(match self.output_mtime(&extra)? {
+                Some(latest) if latest > previous_mtime => latest,
+                _ => previous_mtime,
+            }),
+            _ => self.output_mtime(&extra)?,
+        };
+
+        let tmp = TempFile::of(archive);
+        let file = File::create(&tmp.path).chain_err(|| format!("failed to create {}", tmp.path.display()))?;
+        let sources = self.sources();
+        let mut old = open()?;
+        compressed_tar_into(file, format, self.pack_config.compression_level, &mut |builder| {
+            for entry in old.entries()?.raw(true) {
+                let mut entry = entry?;
+                let header = entry.header().clone();
+                builder.append(&header, &mut entry)?;
+            }
+            append_all(builder, &extra, &sources)
+        })?;
+        tmp.persist(archive)?;
+        if let Some(mtime) = mtime {
+            set_output_mtime(archive, mtime)?;
+        }
+        debug!("appended {} files to {}", extra.len(), archive.display());
+        Ok(())
+    }
+
+    /// writes the binaries and the entries into `out` as a self-extracting archive: a POSIX shell
+    /// script followed by a tar.gz payload. Running the script extracts the files into the
+    /// current directory with `gzip` and `tar`.
+    ///
+    /// The script is made executable on Unix. Like `create_archive`, it is an error if `out` is
+    /// itself one of the files to pack.
+    pub fn create_self_extracting(&self, out: &Path) -> Result<()> {
+        let _lock = self.lock()?;
+        ensure_parent_dir(out)?;
+        let entries = self.archive_entries()?;
+        self.check_output(out)?;
+        check_not_packed(out, &entries)?;
+        let package = self.package()?;
+        let mut script = vec![
+            "#!/bin/sh".to_string(),
+            format!(
+                "# {} {}, a self-extracting archive. Run it to extract the files into the current directory.",
+                package.name(),
+                package.version()
+            ),
+            "set -e".to_string(),
+        ];
+        // the payload starts after this line and `exit`
+        let payload_line = script.len() + 3;
+        script.push(format!("tail -n +{} \"$0\" | gzip -dc | tar -xf -", payload_line));
+        script.push("exit 0".to_string());
+        let mut file = File::create(out).chain_err(|| format!("failed to create {}", out.display()))?;
+        for line in &script {
+            writeln!(file, "{}", line)?;
+        }
+        let sources = self.sources();
+        compressed_tar_into(
+            &mut file,
+            ArchiveFormat::TarGz,
+            self.pack_config.compression_level,
+            &mut |builder| append_all(builder, &entries, &sources),
+        )?;
+        set_mode(out, 0o755)?;
+        self.touch_output(out, &entries)?;
+        debug!("wrote self-extracting archive {}", out.display());
+        Ok(())
+    }
+
+    /// writes the binaries and the entries into `out` as an OCI image layer tarball.
+    ///
+    /// Files are placed under `prefix` (e.g. `/usr/local/bin`) keeping their destinations.
+    /// The entries are those of `create_archive`, sorted by the destination, and their metadata
+    /// is normalized, so the layer is reproducible. No whiteout entries are written.
+    ///
+    /// Returns the diff digest of the layer (`sha256:<hex>`), which can be referenced
+    /// from an image manifest.
+    pub fn create_oci_layer(&self, out: &Path, prefix: &str) -> Result<String> {
+        let prefix = Path::new(prefix.trim_left_matches('/'));
+        let _lock = self.lock()?;
+        self.check_output(out)?;
+        ensure_parent_dir(out)?;
+        let entries = self.archive_entries()?;
+        check_not_packed(out, &entries)?;
+        let mut builder = Builder::new(DigestWriter::new(File::create(out)?));
+        for entry in &entries {
+            append_normalized(&mut builder, entry, &prefix.join(&entry.dest), &self.sources())?;
+        }
+        let digest = builder.into_inner()?.finish()?;
+        self.touch_output(out, &entries)?;
+        debug!("wrote OCI layer {:?}: sha256:{}", out, digest);
+        Ok(format!("sha256:{}", digest))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArchiveFormat;
+    use {EntrySource, PackEntry};
+    use flate2::read::GzDecoder;
+    use std::fs::File;
+    use std::path::PathBuf;
+    use tar::Archive;
+    use test_util::{self, TempDir};
+
+    #[test]
+    fn oci_layer_has_binaries_under_prefix_and_is_reproducible() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]");
+        let out = dir.path().join("layer.tar");
+        let digest = pack.create_oci_layer(&out, "/usr/local/bin").unwrap();
+        let mut archive = Archive::new(File::open(&out).unwrap());
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["usr/local/bin/README.md", "usr/local/bin/foo"]);
+        assert_eq!(pack.create_oci_layer(&out, "/usr/local/bin").unwrap(), digest);
+    }
+
+    #[test]
+    fn writing_the_archive_into_the_packed_files_is_an_error() {
+        let dir = TempDir::new();
+        dir.write("dist/app.js", "app");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/*\"]");
+        let out = dir.path().join("dist/foo.zip");
+        let e = pack.create_archive(&out, ArchiveFormat::Zip).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "the output {} would be matched by files entry `dist/*`. write it outside of the packed files",
+                out.display()
+            )
+        );
+        assert!(!out.exists());
+        assert!(pack.create_archive(&dir.path().join("dist/sub/foo.zip"), ArchiveFormat::Zip).is_ok());
+
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/app.js\", \"*.sha256\"]");
+        let e = pack.create_archive_with_checksum(&dir.path().join("foo.zip"), ArchiveFormat::Zip)
+            .err()
+            .unwrap();
+        let checksum = dir.path().join("foo.zip.sha256");
+        assert!(e.to_string().starts_with(&format!("the output {} would be", checksum.display())), "{}", e);
+        assert!(!dir.path().join("foo.zip").exists());
+
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/*\", \"!dist/*.zip\"]");
+        pack.create_archive(&out, ArchiveFormat::Zip).unwrap();
+        assert!(pack.create_archive(&out, ArchiveFormat::Zip).is_ok());
+
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/*.{js,zip}\"]");
+        let e = pack.create_archive(&out, ArchiveFormat::Zip).err().unwrap();
+        assert!(e.to_string().contains("would be matched by files entry `dist/*.{js,zip}`"), "{}", e);
+
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/*\"]\nexclude-extensions = [\"zip\"]");
+        assert!(pack.create_archive(&out, ArchiveFormat::Zip).is_ok());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn extended_attributes_round_trip_as_pax_headers() {
+        use xattr;
+
+        let dir = TempDir::new();
+        let readme = dir.write("README.md", "readme");
+        xattr::set(&readme, "user.cargo-pack", b"packed").unwrap();
+        dir.binary("foo");
+        let config = test_util::config();
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]\nfiles = [\"README.md\"]");
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_preserve_xattrs(true)
+            .build()
+            .unwrap();
+        let out = dir.path().join("foo.tar.gz");
+        pack.create_archive(&out, ArchiveFormat::TarGz).unwrap();
+        let mut archive = Archive::new(GzDecoder::new(File::open(&out).unwrap()));
+        let mut xattrs = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().into_owned();
+            if let Some(extensions) = entry.pax_extensions().unwrap() {
+                for extension in extensions {
+                    let extension = extension.unwrap();
+                    xattrs.push((path.clone(), extension.key().unwrap().to_string(), extension.value_bytes().to_vec()));
+                }
+            }
+        }
+        assert_eq!(
+            xattrs,
+            vec![("README.md".to_string(), "SCHILY.xattr.user.cargo-pack".to_string(), b"packed".to_vec())]
+        );
+    }
+
+    #[test]
+    fn appending_a_packed_destination_is_an_error() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        let extra = dir.write("extra/README.md", "extra");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]");
+        let out = dir.path().join("foo.tar.gz");
+        pack.create_archive(&out, ArchiveFormat::TarGz).unwrap();
+        let e = pack.append_to_tar(&out, &[(extra.clone(), PathBuf::from("README.md"))])
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), format!("README.md is already in {}", out.display()));
+        let e = pack.append_to_tar(
+            &out,
+            &[(extra.clone(), PathBuf::from("NOTES.md")), (extra.clone(), PathBuf::from("NOTES.md"))],
+        ).err()
+            .unwrap();
+        assert_eq!(e.to_string(), format!("NOTES.md is already in {}", out.display()));
+        // failing after the temporary file is created
+        assert!(
+            pack.append_to_tar(&out, &[(dir.path().join("missing"), PathBuf::from("NOTES.md"))])
+                .is_err()
+        );
+        assert!(!dir.path().join("foo.tar.gz.tmp").exists());
+
+        pack.append_to_tar(&out, &[(extra, PathBuf::from("NOTES.md"))]).unwrap();
+        let mut archive = Archive::new(GzDecoder::new(File::open(&out).unwrap()));
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec!["README.md", "foo", "NOTES.md"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn self_extracting_script_extracts_the_files() {
+        use cargo::util::paths;
+        use std::fs;
+        use std::process::Command;
+
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]");
+        let out = dir.path().join("foo.run");
+        pack.create_self_extracting(&out).unwrap();
+        let extracted = dir.path().join("extracted");
+        fs::create_dir(&extracted).unwrap();
+        let status = Command::new(&out).current_dir(&extracted).status().unwrap();
+        assert!(status.success());
+        assert_eq!(paths::read(&extracted.join("README.md")).unwrap(), "readme");
+        assert_eq!(paths::read(&extracted.join("foo")).unwrap(), "binary");
+    }
+
+    #[test]
+    fn output_mtime_sets_the_mtime_of_the_archive() {
+        use filetime::{self, FileTime};
+        use std::fs;
+        use std::time::{Duration, UNIX_EPOCH};
+        use OutputMtime;
+
+        let dir = TempDir::new();
+        let readme = dir.write("README.md", "readme");
+        let binary = dir.binary("foo");
+        filetime::set_file_times(&readme, FileTime::zero(), FileTime::from_seconds_since_1970(2_000, 0)).unwrap();
+        filetime::set_file_times(&binary, FileTime::zero(), FileTime::from_seconds_since_1970(1_000, 0)).unwrap();
+        let config = test_util::config();
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]\nfiles = [\"README.md\"]");
+        let out = dir.path().join("foo.zip");
+        let mtime = |output_mtime| {
+            test_util::builder(&config, &dir, &manifest)
+                .with_output_mtime(output_mtime)
+                .build()
+                .unwrap()
+                .create_archive(&out, ArchiveFormat::Zip)
+                .unwrap();
+            fs::metadata(&out).unwrap().modified().unwrap()
+        };
+        let at = UNIX_EPOCH + Duration::from_secs(1_234_567_890);
+        assert_eq!(mtime(OutputMtime::At(at)), at);
+        assert_eq!(mtime(OutputMtime::LatestSource), UNIX_EPOCH + Duration::from_secs(2_000));
+        assert!(mtime(OutputMtime::Keep) > at);
+    }
+
+    #[test]
+    fn compressed_formats_are_stored_in_zip_archives() {
+        use zip::{CompressionMethod, ZipArchive};
+
+        let dir = TempDir::new();
+        dir.write("photo.jpg", &"jpeg".repeat(256));
+        dir.write("notes.txt", &"text".repeat(256));
+        dir.binary("foo");
+        let config = test_util::config();
+        let files = "files = [\"photo.jpg\", \"notes.txt\"]";
+        let pack = test_util::pack(&config, &dir, files);
+        let out = dir.path().join("foo.zip");
+        pack.create_archive(&out, ArchiveFormat::Zip).unwrap();
+        let mut zip = ZipArchive::new(File::open(&out).unwrap()).unwrap();
+        assert_eq!(zip.by_name("photo.jpg").unwrap().compression(), CompressionMethod::Stored);
+        assert_eq!(zip.by_name("notes.txt").unwrap().compression(), CompressionMethod::Deflated);
+
+        let pack = test_util::pack(&config, &dir, &format!("{}\nstore-extensions = [\"txt\"]", files));
+        pack.create_archive(&out, ArchiveFormat::Zip).unwrap();
+        let mut zip = ZipArchive::new(File::open(&out).unwrap()).unwrap();
+        assert_eq!(zip.by_name("photo.jpg").unwrap().compression(), CompressionMethod::Deflated);
+        assert_eq!(zip.by_name("notes.txt").unwrap().compression(), CompressionMethod::Stored);
+    }
+
+    #[test]
+    fn finalize_returns_the_checksum_file_with_the_archive() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]\nartifact-dir = \"dist\"");
+        let summaries = pack.finalize().unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].checksum, None);
+
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "files = [\"README.md\"]\nartifact-dir = \"dist\"\ninclude-checksums = true",
+        );
+        let summary = pack.finalize().unwrap().remove(0);
+        let mut checksum = summary.path.clone().into_os_string();
+        checksum.push(".sha256");
+        assert_eq!(summary.checksum, Some(PathBuf::from(checksum)));
+        let line = ::cargo::util::paths::read(summary.checksum.as_ref().unwrap()).unwrap();
+        let name = summary.path.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(line.trim_right().ends_with(&format!("  {}", name)), "{}", line);
+    }
+
+    #[test]
+    fn failing_to_write_an_archive_keeps_the_previous_one() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]");
+        for &(format, name) in &[(ArchiveFormat::TarGz, "foo.tar.gz"), (ArchiveFormat::Zip, "foo.zip")] {
+            let out = dir.path().join(name);
+            pack.create_archive(&out, format).unwrap();
+            let previous = ::cargo::util::paths::read_bytes(&out).unwrap();
+            let mut entries = pack.archive_entries().unwrap();
+            entries.push(PackEntry {
+                source: EntrySource::Path(dir.path().join("missing")),
+                dest: PathBuf::from("missing"),
+                mode: None,
+            });
+            assert!(super::write_archive(&out, format, &pack.compress_options(), &entries, &pack.sources()).is_err());
+            assert_eq!(::cargo::util::paths::read_bytes(&out).unwrap(), previous);
+            assert!(!dir.path().join(format!("{}.tmp", name)).exists());
+        }
+    }
+}
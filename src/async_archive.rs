@@ -0,0 +1,76 @@
+//! writing archives from async code without blocking the runtime. Enabled by the `async` feature.
+
+use CargoPack;
+use archive::{set_output_mtime, write_archive, ArchiveFormat, ArchiveSummary, Sources};
+use cache::ContentCache;
+use error::*;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::task::{self, JoinHandle};
+
+/// an archive being written on the blocking thread pool of tokio. Resolves to the summary of the
+/// archive, as `CargoPack::create_archive` returns.
+pub struct ArchiveTask {
+    handle: JoinHandle<Result<ArchiveSummary>>,
+}
+
+impl Future for ArchiveTask {
+    type Output = Result<ArchiveSummary>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match Pin::new(&mut self.handle).poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Ok(result)) => Poll::Ready(result),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(format!("failed to write the archive: {}", e).into())),
+        }
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    fn spawn_archive(&self, out: &Path, format: ArchiveFormat) -> Result<ArchiveTask> {
+        let lock = self.lock()?;
+        let entries = self.archive_entries()?;
+        self.check_output(out)?;
+        let mtime = self.output_mtime(&entries)?;
+        let out = out.to_path_buf();
+        let compress = self.compress_options();
+        let xattrs = self.preserve_xattrs;
+        let handle = task::spawn_blocking(move || {
+            let _lock = lock;
+            // the cache of the builder belongs to this thread
+            let cache = ContentCache::new(0);
+            let sources = Sources {
+                cache: &cache,
+                xattrs: xattrs,
+            };
+            let summary = write_archive(&out, format, &compress, &entries, &sources)?;
+            if let Some(mtime) = mtime {
+                set_output_mtime(&out, mtime)?;
+            }
+            Ok(summary)
+        });
+        Ok(ArchiveTask { handle: handle })
+    }
+
+    /// writes the same tarball as `create_archive` without blocking the tokio runtime.
+    /// `format` must be one of the tarball formats.
+    ///
+    /// The files to pack are resolved before returning, on the calling thread, as `CargoPack`
+    /// cannot be sent to another thread. Reading, compressing and writing then run synchronously
+    /// on the blocking thread pool, so this only keeps them off the async workers; it is not
+    /// faster than `create_archive`. The content cache is not used. It must be called within a
+    /// tokio runtime.
+    pub fn create_tar_async(&self, out: &Path, format: ArchiveFormat) -> Result<ArchiveTask> {
+        if format == ArchiveFormat::Zip {
+            return Err("zip is not a tarball format. use create_zip_async".into());
+        }
+        self.spawn_archive(out, format)
+    }
+
+    /// writes the same zip archive as `create_archive` as `create_tar_async` does.
+    pub fn create_zip_async(&self, out: &Path) -> Result<ArchiveTask> {
+        self.spawn_archive(out, ArchiveFormat::Zip)
+    }
+}
@@ -0,0 +1,141 @@
+//! packing many workspace members at once, going on past failures
+
+use {CargoPack, CargoPackBuilder, matching_members};
+use error::*;
+use plan::{binary_collisions, collisions_error};
+
+/// what `CargoPackBuilder::pack_all` did for each member, in the order of the workspace members
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// names of the members packed
+    pub succeeded: Vec<String>,
+    /// names of the members which failed to load or to pack, with the errors
+    pub failed: Vec<(String, Error)>,
+}
+
+impl BatchReport {
+    /// returns whether every member was packed
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl<'cfg> CargoPackBuilder<'cfg> {
+    /// creates a CargoPack value for each member matching the package name pattern, or for every
+    /// member without a package name, and calls `pack` with it.
+    ///
+    /// A member fails if its config cannot be loaded or `pack` returns an error. Failures do not
+    /// stop the batch: every member is tried and the report tells which failed and why. Only
+    /// an error loading the workspace itself is returned as `Err`.
+    ///
+    /// Binaries of the members with `check-binary-collisions` are checked together before any is
+    /// packed. The members packing binaries at the same destination as another member fail
+    /// without calling `pack`.
+    pub fn pack_all<F>(self, mut pack: F) -> Result<BatchReport>
+    where
+        F: FnMut(&CargoPack<'cfg>) -> Result<()>,
+    {
+        let names = {
+            let ws = self.load_workspace()?;
+            match self.package_name {
+                Some(ref pattern) => matching_members(&ws, pattern)?,
+                None => ws.members().collect(),
+            }.iter()
+                .map(|p| p.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        let mut packs = names
+            .into_iter()
+            .map(|name| {
+                let result = self.clone().with_package_name(name.clone()).build();
+                (name, result)
+            })
+            .collect::<Vec<_>>();
+        let mut binaries = Vec::new();
+        let mut destinations = Vec::new();
+        for &mut (_, ref mut result) in &mut packs {
+            let packed = match *result {
+                Ok(ref cargo_pack) if cargo_pack.checks_binary_collisions() => cargo_pack.packed_binaries(),
+                _ => Ok(Vec::new()),
+            };
+            match packed {
+                Ok(packed) => {
+                    destinations.push(packed.iter().map(|&(_, _, ref dest)| dest.clone()).collect::<Vec<_>>());
+                    binaries.extend(packed);
+                }
+                Err(e) => {
+                    destinations.push(Vec::new());
+                    *result = Err(e);
+                }
+            }
+        }
+        let collisions = binary_collisions(binaries);
+        let mut report = BatchReport::default();
+        for ((name, result), destinations) in packs.into_iter().zip(destinations) {
+            let result = result.and_then(|cargo_pack| {
+                if collisions.iter().any(|&(ref dest, _)| destinations.contains(dest)) {
+                    Err(collisions_error(&collisions))
+                } else {
+                    pack(&cargo_pack)
+                }
+            });
+            match result {
+                Ok(()) => report.succeeded.push(name),
+                Err(e) => {
+                    warn!("failed to pack {}: {}", name, e);
+                    report.failed.push((name, e));
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_util::{self, TempDir};
+
+    /// writes a workspace of `api` and `admin`, both with a binary `server`, and builds it
+    fn workspace(admin: &str) -> TempDir {
+        let dir = TempDir::new();
+        dir.write("Cargo.toml", "[workspace]\nmembers = [\"api\", \"admin\"]\n");
+        let bin = "[[bin]]\nname = \"server\"\npath = \"src/main.rs\"\n\n[package.metadata.pack]\n\
+                   check-binary-collisions = true\n";
+        dir.package("api", "api", bin);
+        dir.package("admin", "admin", &format!("{}{}", bin, admin));
+        dir.binary("server");
+        dir
+    }
+
+    #[test]
+    fn binaries_of_members_colliding_are_errors() {
+        let dir = workspace("");
+        let config = test_util::config();
+        let builder =
+            test_util::builder(&config, &dir, &dir.path().join("Cargo.toml")).with_package_name("*".to_string());
+
+        let e = builder.clone().build_all().err().unwrap().to_string();
+        assert!(e.contains("server: api/server, admin/server"), "{}", e);
+
+        let mut packed = Vec::new();
+        let report = builder
+            .pack_all(|pack| {
+                packed.push(pack.package()?.name().to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert!(packed.is_empty());
+        let failed = report.failed.iter().map(|&(ref name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(failed, vec!["api", "admin"]);
+    }
+
+    #[test]
+    fn renamed_binaries_of_members_do_not_collide() {
+        let dir = workspace("rename-binaries = { \"admin/server\" = \"admin-server\" }\n");
+        let config = test_util::config();
+        let builder =
+            test_util::builder(&config, &dir, &dir.path().join("Cargo.toml")).with_package_name("*".to_string());
+        assert_eq!(builder.clone().build_all().unwrap().len(), 2);
+        assert_eq!(builder.pack_all(|_| Ok(())).unwrap().succeeded, vec!["api", "admin"]);
+    }
+}
@@ -0,0 +1,104 @@
+//! shell-like `{a,b}` alternations in glob patterns, which the glob crate does not support
+
+use error::*;
+
+/// returns the byte positions of the first unescaped `{` and its matching `}` in `pattern`,
+/// and the positions of the commas between them which are not in nested braces
+fn first_group(pattern: &str) -> Result<Option<(usize, usize, Vec<usize>)>> {
+    let mut open = None;
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    let mut chars = pattern.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '{' => {
+                if open.is_none() {
+                    open = Some(i);
+                }
+                depth += 1;
+            }
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some((open.unwrap(), i, commas)));
+                }
+            }
+            ',' if depth == 1 => commas.push(i),
+            _ => (),
+        }
+    }
+    match open {
+        Some(_) => Err(format!("unclosed `{{` in `{}`", pattern).into()),
+        None => Ok(None),
+    }
+}
+
+/// expands the groups of `pattern`, keeping escapes
+fn expand_escaped(pattern: &str) -> Result<Vec<String>> {
+    let (open, close, commas) = match first_group(pattern)? {
+        Some(group) => group,
+        None => return Ok(vec![pattern.to_string()]),
+    };
+    let mut alternatives = Vec::new();
+    if commas.is_empty() {
+        // `{a}` is not an alternation and stays as it is, as in shells
+        for inner in expand_escaped(&pattern[open + 1..close])? {
+            alternatives.push(format!("\\{{{}\\}}", inner));
+        }
+    } else {
+        let mut start = open + 1;
+        for end in commas.into_iter().chain(Some(close)) {
+            alternatives.extend(expand_escaped(&pattern[start..end])?);
+            start = end + 1;
+        }
+    }
+    let prefix = &pattern[..open];
+    let suffixes = expand_escaped(&pattern[close + 1..])?;
+    let mut expanded = Vec::new();
+    for alternative in &alternatives {
+        for suffix in &suffixes {
+            expanded.push(format!("{}{}{}", prefix, alternative, suffix));
+        }
+    }
+    Ok(expanded)
+}
+
+/// expands each `{a,b,...}` group in `pattern` into the patterns having one of the alternatives.
+///
+/// Groups can be nested, e.g. `{a,b{1,2}}` expands to `a`, `b1` and `b2`. `\{`, `\}` and `\,`
+/// stand for literal characters. It is an error for a `{` to be unclosed.
+pub fn expand(pattern: &str) -> Result<Vec<String>> {
+    Ok(expand_escaped(pattern)?
+        .into_iter()
+        .map(|p| p.replace("\\{", "{").replace("\\}", "}").replace("\\,", ","))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand;
+
+    #[test]
+    fn groups_expand_to_their_alternatives() {
+        assert_eq!(expand("dist/{js,css}/**").unwrap(), vec!["dist/js/**", "dist/css/**"]);
+        assert_eq!(expand("{a,b}.{x,y}").unwrap(), vec!["a.x", "a.y", "b.x", "b.y"]);
+        assert_eq!(expand("plain/*").unwrap(), vec!["plain/*"]);
+    }
+
+    #[test]
+    fn nested_groups_expand_to_their_alternatives() {
+        assert_eq!(expand("{a,b{1,2}}").unwrap(), vec!["a", "b1", "b2"]);
+        assert_eq!(expand("x/{a,{b,c}/d}").unwrap(), vec!["x/a", "x/b/d", "x/c/d"]);
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        assert_eq!(expand("\\{a,b\\}").unwrap(), vec!["{a,b}"]);
+        assert_eq!(expand("{a\\,b,c}").unwrap(), vec!["a,b", "c"]);
+        assert_eq!(expand("{a}").unwrap(), vec!["{a}"]);
+        assert_eq!(expand("{a,b").err().unwrap().to_string(), "unclosed `{` in `{a,b`");
+    }
+}
@@ -0,0 +1,53 @@
+//! the generated `BUILD-INFO` file recording where an archive comes from
+
+use {CargoPack, EntrySource, PackEntry};
+use error::*;
+use std::env;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use time;
+
+/// returns the build time in seconds since the epoch, `SOURCE_DATE_EPOCH` if set
+fn build_time() -> Result<u64> {
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse()
+            .chain_err(|| format!("invalid SOURCE_DATE_EPOCH `{}`", epoch)),
+        Err(_) => Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)),
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// returns the `BUILD-INFO` entry when `include-build-info` is enabled.
+    ///
+    /// It is a text file of `key: value` lines: the name and the version of the package, the git
+    /// commit (`unknown` outside of a git repository), the build time in RFC 3339 and the version
+    /// of rustc. The build time is `SOURCE_DATE_EPOCH` if set, so that archives can be reproduced.
+    pub fn build_info(&self) -> Result<Option<PackEntry>> {
+        if !self.pack_config.include_build_info.unwrap_or(false) {
+            return Ok(None);
+        }
+        let package = self.package()?;
+        let commit = self.git_commit()?.unwrap_or_else(|| "unknown".to_string());
+        let built = time::at_utc(time::Timespec::new(build_time()? as i64, 0));
+        let rustc = self.ws().config().rustc()?;
+        let rustc_version = rustc.verbose_version.lines().next().unwrap_or("unknown");
+        let content = format!(
+            "name: {}\nversion: {}\ncommit: {}\nbuilt: {}\nrustc: {}\n",
+            package.name(),
+            package.version(),
+            commit,
+            built.rfc3339(),
+            rustc_version
+        );
+        Ok(Some(PackEntry {
+            source: EntrySource::Content(content.into_bytes()),
+            dest: PathBuf::from("BUILD-INFO"),
+            mode: None,
+        }))
+    }
+}
@@ -0,0 +1,90 @@
+//! cache of file contents shared by the archive writers
+
+use error::*;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// contents of source files, kept up to a total size so that writing several archives reads each
+/// file once. A cached content is used only while the size and mtime of the file stay the same.
+#[derive(Debug)]
+pub struct ContentCache {
+    limit: u64,
+    files: Mutex<CachedFiles>,
+}
+
+#[derive(Debug, Default)]
+struct CachedFiles {
+    used: u64,
+    contents: HashMap<PathBuf, (u64, SystemTime, Arc<Vec<u8>>)>,
+}
+
+impl ContentCache {
+    /// creates a cache holding up to `limit` bytes. `0` disables caching.
+    pub fn new(limit: u64) -> Self {
+        ContentCache {
+            limit: limit,
+            files: Mutex::new(CachedFiles::default()),
+        }
+    }
+
+    /// returns the content of `path`, reading it only the first time.
+    /// Returns `None` if it does not fit in the cache, or if a thread panicked while using the
+    /// cache and poisoned it, so the caller should read the file itself.
+    pub fn get(&self, path: &Path) -> Result<Option<Arc<Vec<u8>>>> {
+        if self.limit == 0 {
+            return Ok(None);
+        }
+        let meta = fs::metadata(path)?;
+        let modified = meta.modified()?;
+        let mut files = match self.files.lock() {
+            Ok(files) => files,
+            // a thread panicked while updating the cache, which may be inconsistent
+            Err(_) => return Ok(None),
+        };
+        if let Some(&(len, mtime, ref content)) = files.contents.get(path) {
+            if len == meta.len() && mtime == modified {
+                return Ok(Some(content.clone()));
+            }
+        }
+        let stale = files.contents.remove(path).map(|(len, _, _)| len).unwrap_or(0);
+        files.used -= stale;
+        if files.used + meta.len() > self.limit {
+            return Ok(None);
+        }
+        let mut content = Vec::new();
+        File::open(path)?.read_to_end(&mut content)?;
+        let content = Arc::new(content);
+        files.used += meta.len();
+        files.contents.insert(path.to_path_buf(), (meta.len(), modified, content.clone()));
+        Ok(Some(content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentCache;
+    use std::sync::Arc;
+    use std::thread;
+    use test_util::TempDir;
+
+    #[test]
+    fn poisoned_cache_is_not_used() {
+        let dir = TempDir::new();
+        let path = dir.write("a.txt", "a");
+        let cache = Arc::new(ContentCache::new(1024));
+        assert_eq!(*cache.get(&path).unwrap().unwrap(), b"a");
+        let poisoning = cache.clone();
+        assert!(
+            thread::spawn(move || {
+                let _files = poisoning.files.lock().unwrap();
+                panic!("poisoning the cache");
+            }).join()
+                .is_err()
+        );
+        assert!(cache.get(&path).unwrap().is_none());
+    }
+}
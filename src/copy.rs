@@ -0,0 +1,360 @@
+//! copying the entries into a directory, and staging them under the target directory
+
+use {CargoPack, EntrySource, PackEntry, ensure_dir, ensure_parent_dir};
+use archive::{entry_digest, file_mode};
+use error::*;
+use filetime::{self, FileTime};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// file in the directory of `CargoPack::copy_to` listing the destinations it copied, so that an
+/// incremental copy removes only the files an earlier copy wrote
+const COPY_MANIFEST: &str = ".cargo-pack-manifest";
+
+/// options of `CargoPack::copy_to`
+#[derive(Debug, Clone, Default)]
+pub struct CopyOptions {
+    /// skips files whose destination is up to date and removes the files an earlier `copy_to`
+    /// copied which are not entries anymore. Other files in the directory are kept. Source files
+    /// are compared by size, mtime and permission bits, generated files by content and permission
+    /// bits. Defaults to `false`, copying every entry.
+    pub incremental: bool,
+    /// hardlinks an entry to an already copied entry with the same content and mode instead of
+    /// copying it again. Contents are compared by sha256 digest. Falls back to copying where
+    /// hardlinks cannot be made, e.g. across devices. Defaults to `false`.
+    pub dedup: bool,
+}
+
+/// what `CargoPack::copy_to` did. Paths are relative to the directory copied to.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CopyReport {
+    /// written files
+    pub copied: Vec<PathBuf>,
+    /// files hardlinked to another copied file. Only with `CopyOptions::dedup`
+    pub linked: Vec<PathBuf>,
+    /// files already up to date
+    pub skipped: Vec<PathBuf>,
+    /// files copied by an earlier `copy_to` which are not entries anymore, removed
+    pub removed: Vec<PathBuf>,
+}
+
+#[cfg(unix)]
+pub(crate) fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+fn is_up_to_date(entry: &PackEntry, dest: &Path) -> Result<bool> {
+    let dest_meta = match fs::metadata(dest) {
+        Ok(meta) => meta,
+        Err(_) => return Ok(false),
+    };
+    match entry.source {
+        EntrySource::Path(ref src) => {
+            let src_meta = fs::metadata(src)?;
+            if entry.mode.unwrap_or_else(|| file_mode(&src_meta)) != file_mode(&dest_meta) {
+                return Ok(false);
+            }
+            Ok(src_meta.len() == dest_meta.len()
+                && FileTime::from_last_modification_time(&src_meta)
+                    == FileTime::from_last_modification_time(&dest_meta))
+        }
+        EntrySource::Content(ref content) => {
+            if content.len() as u64 != dest_meta.len() || entry.mode.map_or(false, |m| m != file_mode(&dest_meta)) {
+                return Ok(false);
+            }
+            let mut existing = Vec::new();
+            File::open(dest)?.read_to_end(&mut existing)?;
+            Ok(existing == *content)
+        }
+    }
+}
+
+fn copy_entry(entry: &PackEntry, dest: &Path) -> Result<()> {
+    ensure_parent_dir(dest)?;
+    match entry.source {
+        EntrySource::Path(ref src) => {
+            fs::copy(src, dest).chain_err(|| format!("failed to copy {} to {}", src.display(), dest.display()))?;
+            let meta = fs::metadata(src)?;
+            let mtime = FileTime::from_last_modification_time(&meta);
+            filetime::set_file_times(dest, mtime, mtime)?;
+        }
+        EntrySource::Content(ref content) => {
+            File::create(dest)?.write_all(content)?;
+        }
+    }
+    if let Some(mode) = entry.mode {
+        set_mode(dest, mode)?;
+    }
+    Ok(())
+}
+
+/// returns the destinations listed in the manifest of `dir`, none without a manifest.
+/// Lines which are not relative paths inside the directory are ignored.
+fn read_copy_manifest(dir: &Path) -> Result<Vec<PathBuf>> {
+    let path = dir.join(COPY_MANIFEST);
+    let mut content = String::new();
+    match File::open(&path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut content)
+                .chain_err(|| format!("failed to read {}", path.display()))?;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::with_chain(e, format!("failed to read {}", path.display()))),
+    }
+    Ok(content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .filter(|dest| {
+            dest.components().all(|c| match c {
+                Component::Normal(_) => true,
+                _ => false,
+            })
+        })
+        .collect())
+}
+
+/// writes the destinations of `entries` into the manifest of `dir`
+fn write_copy_manifest(dir: &Path, entries: &[PackEntry]) -> Result<()> {
+    let path = dir.join(COPY_MANIFEST);
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&entry.dest.to_string_lossy().replace('\\', "/"));
+        content.push('\n');
+    }
+    File::create(&path)
+        .and_then(|mut f| f.write_all(content.as_bytes()))
+        .chain_err(|| format!("failed to write {}", path.display()))
+}
+
+/// removes the files of the manifest of `dir` which are not destinations of `entries`.
+/// Directories are left as they are, and symlinks are removed without following them.
+fn remove_stale(entries: &[PackEntry], dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for dest in read_copy_manifest(dir)? {
+        if entries.iter().any(|entry| entry.dest == dest) {
+            continue;
+        }
+        let path = dir.join(&dest);
+        match fs::symlink_metadata(&path) {
+            Ok(ref meta) if !meta.is_dir() => {
+                fs::remove_file(&path).chain_err(|| format!("failed to remove {}", path.display()))?;
+                removed.push(dest);
+            }
+            _ => (),
+        }
+    }
+    Ok(removed)
+}
+
+/// copies `entries` into `dir`, placing each at its destination
+fn copy_entries(entries: &[PackEntry], dir: &Path, options: &CopyOptions) -> Result<CopyReport> {
+    ensure_dir(dir)?;
+    let mut report = CopyReport::default();
+    // (digest, mode) to the first destination having the content
+    let mut staged: HashMap<(String, Option<u32>), PathBuf> = HashMap::new();
+    for entry in entries {
+        let dest = dir.join(&entry.dest);
+        if options.incremental && is_up_to_date(entry, &dest)? {
+            report.skipped.push(entry.dest.clone());
+            continue;
+        }
+        if !options.dedup {
+            copy_entry(entry, &dest)?;
+            report.copied.push(entry.dest.clone());
+            continue;
+        }
+        // the destination may be a hardlink made by an earlier run. Writing into it would
+        // also change the file it is linked to
+        if dest.exists() {
+            fs::remove_file(&dest)?;
+        }
+        let key = (entry_digest(entry)?, entry.mode);
+        if let Some(original) = staged.get(&key) {
+            ensure_parent_dir(&dest)?;
+            match fs::hard_link(original, &dest) {
+                Ok(()) => {
+                    report.linked.push(entry.dest.clone());
+                    continue;
+                }
+                Err(e) => debug!("cannot hardlink {} to {}: {}", dest.display(), original.display(), e),
+            }
+        }
+        copy_entry(entry, &dest)?;
+        report.copied.push(entry.dest.clone());
+        staged.entry(key).or_insert(dest);
+    }
+    debug!("copied to {}: {:?}", dir.display(), report);
+    Ok(report)
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// copies the binaries and the entries, the same files as `stage`, into `dir`, placing each
+    /// at its destination. It is an error for them to fail the checks of `plan`, or for a copy to
+    /// be matched by `files`, so that copying into the packed files fails before copying anything.
+    ///
+    /// The destinations copied are listed in `.cargo-pack-manifest` in `dir`, which an
+    /// incremental copy reads to remove the files no longer packed.
+    pub fn copy_to(&self, dir: &Path, options: &CopyOptions) -> Result<CopyReport> {
+        let _lock = self.lock()?;
+        let entries = self.archive_entries()?;
+        self.check_copies(&entries, dir)?;
+        let mut report = copy_entries(&entries, dir, options)?;
+        if options.incremental {
+            report.removed = remove_stale(&entries, dir)?;
+        }
+        write_copy_manifest(dir, &entries)?;
+        Ok(report)
+    }
+
+    /// fails if a copy of `entries` into `dir`, or the manifest of `copy_to`, would be packed
+    fn check_copies(&self, entries: &[PackEntry], dir: &Path) -> Result<()> {
+        self.check_output(&dir.join(COPY_MANIFEST))?;
+        for entry in entries {
+            self.check_output(&dir.join(&entry.dest))?;
+        }
+        Ok(())
+    }
+
+    /// returns the directory `stage` copies the entries into, `pack/staging/<name>` in the
+    /// target directory. It is not created.
+    pub fn staging_dir(&self) -> Result<PathBuf> {
+        Ok(self.target_dir()
+            .join("pack")
+            .join("staging")
+            .join(self.package()?.name()))
+    }
+
+    /// copies the binaries and the entries, what `create_archive` packs, into a clean
+    /// `staging_dir` and calls `f` with it, e.g. to build an image or an archive of a format this
+    /// crate does not write. The lock is released before calling `f`.
+    ///
+    /// The directory is removed when `f` succeeds. When copying or `f` fails, it is kept for
+    /// inspection, which is logged, and removed by the next `stage`.
+    pub fn stage<T, F: FnOnce(&Path) -> Result<T>>(&self, f: F) -> Result<T> {
+        let dir = self.staging_dir()?;
+        let staged = {
+            let _lock = self.lock()?;
+            if dir.exists() {
+                fs::remove_dir_all(&dir).chain_err(|| format!("failed to clean {}", dir.display()))?;
+            }
+            self.archive_entries()
+                .and_then(|entries| self.check_copies(&entries, &dir).map(|_| entries))
+                .and_then(|entries| copy_entries(&entries, &dir, &CopyOptions::default()))
+        };
+        match staged.and_then(|_| f(&dir)) {
+            Ok(value) => {
+                fs::remove_dir_all(&dir).chain_err(|| format!("failed to remove {}", dir.display()))?;
+                Ok(value)
+            }
+            Err(e) => {
+                warn!("staging failed. the staged files are kept in {}", dir.display());
+                Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CopyOptions, set_mode};
+    use std::path::PathBuf;
+    use test_util::{self, TempDir};
+
+    #[test]
+    fn copy_to_copies_binaries_and_recopies_changed_modes() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]");
+        let out = dir.path().join("out");
+        let options = CopyOptions {
+            incremental: true,
+            ..CopyOptions::default()
+        };
+        let report = pack.copy_to(&out, &options).unwrap();
+        assert_eq!(report.copied.len(), 2);
+        assert!(report.copied.contains(&PathBuf::from("README.md")));
+
+        let report = pack.copy_to(&out, &options).unwrap();
+        assert!(report.copied.is_empty());
+        assert_eq!(report.skipped.len(), 2);
+
+        if cfg!(unix) {
+            set_mode(&out.join("README.md"), 0o600).unwrap();
+            set_mode(&dir.path().join("README.md"), 0o644).unwrap();
+            let report = pack.copy_to(&out, &options).unwrap();
+            assert_eq!(report.copied, vec![PathBuf::from("README.md")]);
+        }
+    }
+
+    #[test]
+    fn incremental_copies_remove_only_the_files_copied_before() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.write("NOTES.md", "notes");
+        dir.binary("foo");
+        let config = test_util::config();
+        let out = dir.path().join("out");
+        let options = CopyOptions {
+            incremental: true,
+            ..CopyOptions::default()
+        };
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\", \"NOTES.md\"]");
+        pack.copy_to(&out, &options).unwrap();
+        let own = dir.write("out/own.txt", "mine");
+
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]");
+        let report = pack.copy_to(&out, &options).unwrap();
+        assert_eq!(report.removed, vec![PathBuf::from("NOTES.md")]);
+        assert!(!out.join("NOTES.md").exists());
+        assert!(own.exists());
+    }
+
+    #[test]
+    fn copying_into_the_packed_files_is_an_error() {
+        let dir = TempDir::new();
+        dir.write("docs/guide.md", "guide");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [{ dir = \"docs\" }]");
+        let out = dir.path().join("docs/copy");
+        let e = pack.copy_to(&out, &CopyOptions::default()).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "the output {} would be matched by files entry `docs`. write it outside of the packed files",
+                out.join(".cargo-pack-manifest").display()
+            )
+        );
+        assert!(!out.exists());
+        pack.copy_to(&dir.path().join("out"), &CopyOptions::default()).unwrap();
+    }
+
+    #[test]
+    fn stage_removes_the_staging_dir_only_on_success() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\"]");
+        let staging_dir = pack.staging_dir().unwrap();
+        let staged = pack.stage(|dir| Ok(dir.join("README.md").exists() && dir.join("foo").exists()));
+        assert!(staged.unwrap());
+        assert!(!staging_dir.exists());
+
+        let e = pack.stage(|_| -> ::error::Result<()> { Err("failed".into()) }).err().unwrap();
+        assert_eq!(e.to_string(), "failed");
+        assert!(staging_dir.join("README.md").exists());
+    }
+}
@@ -0,0 +1,89 @@
+//! describing the contents of a package as a manifest of cargo-dist style release tools
+
+use {CargoPack, EntrySource};
+use archive::entry_digest;
+use error::*;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// a manifest in the shape of `dist-manifest.json` of cargo-dist, describing the binaries and files
+/// of a package as artifacts of a release. Created by `CargoPack::to_dist_manifest` and
+/// written with serde, e.g. `serde_json::to_string(&manifest)`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DistManifest {
+    /// the releases, only the package
+    pub releases: Vec<DistRelease>,
+    /// the artifacts by name
+    pub artifacts: BTreeMap<String, DistArtifact>,
+}
+
+/// an app released, an entry of `DistManifest::releases`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DistRelease {
+    /// name of the package
+    pub app_name: String,
+    /// version of the package
+    pub app_version: String,
+    /// names of the artifacts of the release, keys of `DistManifest::artifacts`
+    pub artifacts: Vec<String>,
+}
+
+/// an artifact of `DistManifest`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DistArtifact {
+    /// the destination of the entry in the package
+    pub name: String,
+    /// `executable` for binaries, `extra-artifact` for other files
+    pub kind: String,
+    /// the target the artifact is built for
+    pub target_triples: Vec<String>,
+    /// absolute path of the source file, `null` for generated files
+    pub path: Option<PathBuf>,
+    /// digests of the content by algorithm. Only `sha256` for now
+    pub checksums: BTreeMap<String, String>,
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// describes the plan of the package as a manifest for release tools reading cargo-dist
+    /// manifests.
+    ///
+    /// Each entry of the plan is an artifact named by its destination. The target is the one
+    /// given to cargo-pack, or the host of rustc. Digests are computed from the contents.
+    pub fn to_dist_manifest(&self) -> Result<DistManifest> {
+        let plan = self.plan()?;
+        let package = self.package()?;
+        let target = match self.target()? {
+            Some(target) => target,
+            None => self.ws().config().rustc()?.host.clone(),
+        };
+        let mut artifacts = BTreeMap::new();
+        for e in &plan.entries {
+            let name = e.entry.dest.to_string_lossy().into_owned();
+            let mut checksums = BTreeMap::new();
+            checksums.insert("sha256".to_string(), entry_digest(&e.entry)?);
+            artifacts.insert(
+                name.clone(),
+                DistArtifact {
+                    name: name,
+                    kind: if e.binary { "executable" } else { "extra-artifact" }.to_string(),
+                    target_triples: vec![target.clone()],
+                    path: match e.entry.source {
+                        EntrySource::Path(ref src) => Some(src.clone()),
+                        EntrySource::Content(_) => None,
+                    },
+                    checksums: checksums,
+                },
+            );
+        }
+        Ok(DistManifest {
+            releases: vec![
+                DistRelease {
+                    app_name: package.name().to_string(),
+                    app_version: package.version().to_string(),
+                    artifacts: artifacts.keys().cloned().collect(),
+                },
+            ],
+            artifacts: artifacts,
+        })
+    }
+}
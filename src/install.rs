@@ -0,0 +1,162 @@
+//! description of what a package installs, for generating package manager manifests
+
+use CargoPack;
+use archive::ArchiveFormat;
+use error::*;
+use serde_json::{self, Value};
+use std::path::PathBuf;
+use template;
+
+/// what the archive of a package installs and where. Serializable, e.g. to JSON with serde_json,
+/// so a frontend can render a Homebrew formula or a scoop manifest from it.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct InstallManifest {
+    /// name of the package
+    pub name: String,
+    /// version of the package
+    pub version: String,
+    /// `package.description`
+    pub description: Option<String>,
+    /// `package.homepage`
+    pub homepage: Option<String>,
+    /// `package.license`
+    pub license: Option<String>,
+    /// file name of the archive, `<name>-<version>-<target>.<format>`. The URL to download
+    /// the archive from is usually a release URL followed by this name.
+    pub archive_name: String,
+    /// URL of the archive from `download-url-template`, if set
+    pub url: Option<String>,
+    /// binaries to install, sorted by the path
+    pub binaries: Vec<InstallBinary>,
+    /// paths in the archive of the files other than binaries, sorted
+    pub files: Vec<PathBuf>,
+}
+
+/// a binary to install
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct InstallBinary {
+    /// name of the command to install the binary as
+    pub name: String,
+    /// path of the binary in the archive
+    pub path: PathBuf,
+}
+
+/// an entry of `CargoPack::release_matrix`
+#[derive(Serialize)]
+struct ReleaseAsset {
+    target: String,
+    format: String,
+    archive_name: String,
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// returns the URL to download the archive named `archive_name` from, rendering
+    /// `download-url-template`, or `None` if it is not set.
+    pub fn artifact_url(&self, archive_name: &str) -> Result<Option<String>> {
+        let template = match self.pack_config.download_url_template {
+            Some(ref template) => template,
+            None => return Ok(None),
+        };
+        let mut vars = self.template_vars(self.package()?)?;
+        vars.push(("archive", archive_name.to_string()));
+        template::render(template, &vars)
+            .map(Some)
+            .chain_err(|| "invalid download-url-template")
+    }
+
+    /// returns the file name of the archive of `format` of the package,
+    /// `<name>-<version>-<target>.<format>`
+    pub fn archive_name(&self, format: ArchiveFormat) -> Result<String> {
+        let stem = template::render("{name}-{version}-{target}", &self.template_vars(self.package()?)?)?;
+        Ok(format!("{}.{}", stem, format))
+    }
+
+    /// returns what the archive written by `create_archive` installs.
+    ///
+    /// The binaries must be built, as `binary_entries` requires.
+    pub fn install_manifest(&self) -> Result<InstallManifest> {
+        let package = self.package()?;
+        let metadata = package.manifest().metadata();
+        let archive_name = self.archive_name(self.format().unwrap_or(ArchiveFormat::TarGz))?;
+        let mut binaries = self.binary_entries(None)?
+            .into_iter()
+            .map(|entry| InstallBinary {
+                name: entry.dest.file_name().unwrap().to_string_lossy().into_owned(),
+                path: entry.dest,
+            })
+            .collect::<Vec<_>>();
+        binaries.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut files = self.entries()?
+            .into_iter()
+            .map(|entry| entry.dest)
+            .collect::<Vec<_>>();
+        files.sort();
+        Ok(InstallManifest {
+            name: package.name().to_string(),
+            version: package.version().to_string(),
+            description: metadata.description.clone(),
+            homepage: metadata.homepage.clone(),
+            license: metadata.license.clone(),
+            url: self.artifact_url(&archive_name)?,
+            archive_name: archive_name,
+            binaries: binaries,
+            files: files,
+        })
+    }
+
+    /// returns the archives a release of `targets` in `formats` consists of, as a JSON array to
+    /// feed to the matrix of a GitHub Actions job with `fromJSON`.
+    ///
+    /// The array has an object for each target and format, in the order of `targets` then
+    /// `formats`, like `{"target": "x86_64-unknown-linux-gnu", "format": "tar.gz",
+    /// "archive_name": "foo-0.1.0-x86_64-unknown-linux-gnu.tar.gz"}`. The archive name is the one of
+    /// `install_manifest`. Nothing is built.
+    pub fn release_matrix(&self, targets: &[&str], formats: &[ArchiveFormat]) -> Result<Value> {
+        let package = self.package()?;
+        let mut assets = Vec::new();
+        for target in targets {
+            let vars = vec![
+                ("name", package.name().to_string()),
+                ("version", package.version().to_string()),
+                ("target", target.to_string()),
+            ];
+            let stem = template::render("{name}-{version}-{target}", &vars)?;
+            for format in formats {
+                assets.push(ReleaseAsset {
+                    target: target.to_string(),
+                    format: format.to_string(),
+                    archive_name: format!("{}.{}", stem, format),
+                });
+            }
+        }
+        serde_json::to_value(&assets).chain_err(|| "failed to write the release matrix")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_util::{self, TempDir};
+
+    #[test]
+    fn artifact_url_renders_the_template() {
+        let dir = TempDir::new();
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "download-url-template = \"https://dl.example.com/{name}/{version}/{archive}\"",
+        );
+        assert_eq!(
+            pack.artifact_url("foo.tar.gz").unwrap(),
+            Some("https://dl.example.com/foo/0.1.0/foo.tar.gz".to_string())
+        );
+    }
+
+    #[test]
+    fn artifact_url_is_none_without_a_template() {
+        let dir = TempDir::new();
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "");
+        assert_eq!(pack.artifact_url("foo.tar.gz").unwrap(), None);
+    }
+}
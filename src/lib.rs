@@ -6,28 +6,176 @@
 //! [package.metadata.pack]
 //! # Not used for now. Reserved for future use
 //! default-packers = ["docker"]
-//! # files to pack in addition to binaries
-//! files = ["README.md"]
+//! # files to pack in addition to binaries. Glob patterns relative to the package root
+//! files = [
+//!     "README.md",
+//!     "logs/**",
+//!     # `!` removes files matched so far
+//!     "!logs/**/*.tmp",
+//!     # `{a,b}` expands to alternatives
+//!     "static/{js,css}/**",
+//!     { glob = "*.so", base = "target/release" },
+//!     # files generated by the build script into OUT_DIR
+//!     { glob = "assets/*", base = "@out-dir" },
+//!     # base can also be @package, @workspace or @artifact-root
+//!     { glob = "*.wasm", base = "@artifact-root" },
+//!     # an entry packed only for the targets matching a cfg expression, see also `target` below
+//!     { glob = "*.dll", base = "lib", cfg = "cfg(windows)" },
+//!     { from = "scripts/run.sh", to = "bin/run", mode = "755" },
+//!     # `to` can contain {name}, {version} and {target} of the package
+//!     { from = "CHANGELOG.md", to = "{name}-{version}/CHANGELOG.md" },
+//!     # a file or a directory outside of the package but in the workspace, placed relative to
+//!     # the workspace root
+//!     { from = "../plugins/foo/assets", base = "@workspace" },
+//!     # files and binaries of other workspace members
+//!     { package = "frontend", files = ["dist/**"] },
+//!     { package = "frontend", artifact = "bin" },
+//!     # every file in a directory. dest-transform is lowercase, prefix:<prefix> or
+//!     # strip-prefix:<prefix>, or a list of them applied in order
+//!     { dir = "Docs", dest-transform = ["strip-prefix:Docs/", "prefix:doc/", "lowercase"] },
+//! ]
+//! # archive format to produce when a packer is not told. One of tar.gz, tar.zst, tar.xz or zip
+//! format = "tar.gz"
+//! # compression level of tarballs. 0 to 9 for tar.gz and tar.xz, 1 to 21 for tar.zst
+//! compression-level = 9
+//! # files stored without compression in zip archives. Defaults to compressed formats like jpg
+//! store-extensions = ["jpg", "png", "mp4"]
+//! # write the text of `package.license` into LICENSE when there is no license-file
+//! materialize-license = true
+//! # add BUILD-INFO with the version, the git commit, the build time and the rustc version
+//! include-build-info = true
+//! # pack license-file as it is, never passed through the transform of the packer
+//! include-license = true
+//! # pack Cargo.lock of the workspace at the root of the package
+//! include-lockfile = true
+//! # layout of the package. `flat` (default) keeps destinations as they are, `fhs` mirrors an
+//! # install prefix: binaries in bin/, man pages in share/man/manN/ and others in share/<name>/
+//! layout = "fhs"
+//! # destinations differing only by case, like README.md and Readme.md, fail packing (`error`,
+//! # default) or are only warned about (`warn`)
+//! case-collisions = "warn"
+//! # gzip man pages in share/man/manN/ into *.N.gz
+//! gzip-man-pages = true
+//! # warn when a file to pack is larger than this size in bytes
+//! warn-file-size = 10485760
+//! # fail before writing anything when the files to pack are larger than this in total, in bytes
+//! max-total-size = 104857600
+//! # binaries to pack. All binary targets of the package when omitted
+//! bins = ["server", "cli"]
+//! # names to pack binaries as by <package>/<binary>, for binaries of members with the same name
+//! rename-binaries = { "api/server" = "api-server", "admin/server" = "admin-server" }
+//! # fail when binaries of several packages land at the same destination
+//! check-binary-collisions = true
+//! # pack only files tracked by git from glob patterns. Files named without a glob are kept
+//! git-tracked-only = true
+//! # directory of files entries with base = "@artifact-root", relative to the package root
+//! artifact-root = "/build/out"
+//! # drop files with these extensions, case-insensitively, and files larger than this size.
+//! # Sizes are in bytes or have a unit: KB, MB, GB, KiB, MiB or GiB
+//! exclude-extensions = ["map", "d"]
+//! exclude-larger-than = "50MB"
+//! # where the archives are downloaded from, for install manifests. {archive} is the file name
+//! download-url-template = "https://dl.example.com/{name}/{version}/{archive}"
+//! # where finalize writes the archive, relative to the package root, and whether it writes
+//! # a .sha256 checksum file next to it
+//! artifact-dir = "dist"
+//! include-checksums = true
+//!
+//! # files written from the config. {name}, {version} and {target} are replaced in the content
+//! [[package.metadata.pack.inline]]
+//! dest = "VERSION"
+//! content = "{version}\n"
+//!
+//! # files added when packing on a host OS: windows, macos, linux and so on
+//! [package.metadata.pack.os.windows]
+//! files = ["scripts/install.ps1"]
+//!
+//! # files added when the target matches a cfg expression of all, any, not and key = "value"
+//! [package.metadata.pack.target.'cfg(all(target_os = "linux", target_arch = "x86_64"))']
+//! files = ["lib/x86_64-linux/*.so"]
 //! ```
 
 #![deny(missing_docs)]
 extern crate cargo;
 #[macro_use]
 extern crate error_chain;
+extern crate filetime;
+extern crate flate2;
+extern crate fs2;
+extern crate glob;
 #[macro_use]
 extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate tar;
+extern crate time;
+#[cfg(feature = "async")]
+extern crate tokio;
 extern crate toml as toml_crate;
+#[cfg(unix)]
+extern crate xattr;
+extern crate xz2;
+extern crate zip;
+extern crate zstd;
+
+mod archive;
+#[cfg(feature = "async")]
+mod async_archive;
+mod batch;
+mod brace;
+mod build_info;
+mod cache;
+mod copy;
+mod dist;
+mod install;
+mod license;
+mod lock;
+mod lockfile;
+mod metadata;
+mod plan;
+mod preflight;
+mod report;
+mod target_cfg;
+mod template;
+#[cfg(test)]
+mod test_util;
+
+pub use archive::{ArchiveFormat, ArchiveSummary, ArchiveWriter, OutputMtime, TarWriter, ZipArchiveWriter};
+#[cfg(feature = "async")]
+pub use async_archive::ArchiveTask;
+pub use batch::BatchReport;
+pub use copy::{CopyOptions, CopyReport};
+pub use dist::{DistArtifact, DistManifest, DistRelease};
+pub use install::{InstallBinary, InstallManifest};
+pub use lock::LockMode;
+pub use metadata::MetadataPack;
+pub use plan::{PACK_JSON_SCHEMA_VERSION, PackPlan, PlanDiff, PlanEntry, PlanJson, PlanJsonEntry};
+pub use report::{FileValidation, Warning};
 
 use cargo::core::Package;
 use cargo::core::Workspace;
-use cargo::util::{paths, toml};
+use cargo::util::{lev_distance, paths, toml};
 use cargo::util::Config;
 use cargo::util::important_paths::find_root_manifest_for_wd;
 use toml_crate::Value;
-use serde::de::DeserializeOwned;
+use cache::ContentCache;
+use serde::de::{self, Deserialize, DeserializeOwned, Deserializer};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::iter;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::str::{self, FromStr};
+use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
 
 /// Errors and related
 pub mod error {
@@ -42,6 +190,15 @@ pub mod error {
             Cargo(::cargo::CargoError)
             /// Cargo error
                 ;
+            Pattern(::glob::PatternError)
+            /// glob pattern error
+                ;
+            Glob(::glob::GlobError)
+            /// glob traversal error
+                ;
+            Zip(::zip::result::ZipError)
+            /// zip archive error
+                ;
         }
     }
 }
@@ -57,133 +214,4081 @@ use error::*;
 /// default-packers = ["docker"]
 /// files = ["README.md"]
 /// ```
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "kebab-case")]
 pub struct PackConfig {
     /// files to pack into other than binaries
-    pub files: Option<Vec<String>>,
+    pub files: Option<Vec<FileEntry>>,
     /// reserved for future usage.
     pub default_packers: Option<Vec<String>>,
+    /// size in bytes above which a file to pack is warned about.
+    pub warn_file_size: Option<u64>,
+    /// total size in bytes of the files to pack, including binaries, above which packing fails.
+    pub max_total_size: Option<u64>,
+    /// archive format to produce when none is given to the packer.
+    pub format: Option<ArchiveFormat>,
+    /// compression level of tarballs, clamped to the range of the format with a warning:
+    /// 0 to 9 for tar.gz and tar.xz (default 6), 1 to 21 for tar.zst (default 3).
+    /// Zip archives are always deflated at the default level.
+    pub compression_level: Option<i32>,
+    /// extensions of files stored without compression in zip archives, compared
+    /// case-insensitively. Defaults to those of compressed formats like `jpg`, `mp4` and `gz`.
+    pub store_extensions: Option<Vec<String>>,
+    /// packs the text of `package.license` as `LICENSE` when the package has no `license-file`.
+    pub materialize_license: Option<bool>,
+    /// packs `package.license-file` as it is, at its file name.
+    pub include_license: Option<bool>,
+    /// packs `Cargo.lock` of the workspace as `Cargo.lock`.
+    pub include_lockfile: Option<bool>,
+    /// packs a generated `BUILD-INFO` recording the version, commit, build time and rustc.
+    pub include_build_info: Option<bool>,
+    /// how destinations are laid out. Defaults to `Layout::Flat`.
+    pub layout: Option<Layout>,
+    /// what to do with destinations differing only by case, which collide on case-insensitive
+    /// file systems like those of macOS and Windows. Defaults to `CaseCollisions::Error`.
+    pub case_collisions: Option<CaseCollisions>,
+    /// names of the binary targets to pack. Defaults to all of them.
+    pub bins: Option<Vec<String>>,
+    /// names to pack binaries as, keyed by `<package>/<binary>`, like `"api/server" = "api-server"`.
+    /// Applies to the binaries of the package and of other members packed with `artifact = "bin"`.
+    pub rename_binaries: Option<BTreeMap<String, String>>,
+    /// fails when binaries of different packages or targets are packed at the same destination.
+    /// With `CargoPackBuilder::build_all` and `pack_all`, the binaries of all members enabling it
+    /// are checked together too, as if packed into one archive.
+    pub check_binary_collisions: Option<bool>,
+    /// drops files untracked by git from the matches of glob patterns.
+    pub git_tracked_only: Option<bool>,
+    /// directory of files entries with `base = "@artifact-root"`, relative to the package root.
+    pub artifact_root: Option<PathBuf>,
+    /// extensions of files not to pack, compared case-insensitively, like `"map"`.
+    pub exclude_extensions: Option<Vec<String>>,
+    /// size above which files are not packed.
+    pub exclude_larger_than: Option<Size>,
+    /// gzips man pages, files in `share/man/man*/` named like `*.1` through `*.9`, and appends `.gz`
+    /// to their names, as distributions ship them. Usually used with `Layout::Fhs`.
+    pub gzip_man_pages: Option<bool>,
+    /// files to pack with the content written in the config.
+    pub inline: Option<Vec<InlineFile>>,
+    /// URL to download the archives from. `{name}`, `{version}` and `{target}` are replaced with
+    /// those of the package and `{archive}` with the file name of the archive.
+    pub download_url_template: Option<String>,
+    /// directory `CargoPack::finalize` writes the archive to, relative to the package root.
+    /// Defaults to `CargoPack::default_output_dir`.
+    pub artifact_dir: Option<PathBuf>,
+    /// writes a `.sha256` checksum file next to the archive written by `CargoPack::finalize`.
+    pub include_checksums: Option<bool>,
+    /// config of each host OS, keyed by the names of `std::env::consts::OS`.
+    ///
+    /// When the config is loaded, `files` of the host OS are appended to `files`. The host is
+    /// the machine running the packer, regardless of the target given by `CargoPackBuilder::with_target`.
+    pub os: Option<BTreeMap<String, OsConfig>>,
+    /// config of the targets matching cfg expressions, keyed by the expressions like
+    /// `cfg(all(target_os = "linux", target_arch = "x86_64"))` as cargo writes them.
+    ///
+    /// When the package is selected, `files` of the sections matching the target given by
+    /// `CargoPackBuilder::with_target`, or the host, are appended to `files`. The cfg of the
+    /// target is printed by rustc. Expressions are checked when the config is loaded.
+    /// Single entries of `files` can have an expression too, as `FileEntry::Conditional`.
+    pub target: Option<BTreeMap<String, OsConfig>>,
 }
 
-/// cargo-pack API
-pub struct CargoPack<'cfg> {
-    ws: Workspace<'cfg>,
-    package_name: Option<String>,
-    pack_config: PackConfig,
+/// config for a host OS, `package.metadata.pack.os.<os>`, or for the targets of a cfg
+/// expression, `package.metadata.pack.target.<cfg>`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct OsConfig {
+    /// files to pack in addition to `files` on the OS or for the targets
+    pub files: Option<Vec<FileEntry>>,
 }
 
-fn lookup(mut value: Value, path: &[&str]) -> Option<Value> {
-    for key in path {
-        match value {
-            Value::Table(mut hm) => {
-                // removing to take the ownership
-                match hm.remove(*key) {
-                    Some(v) => value = v,
-                    None => return None,
+/// a file written from the config, an entry of `package.metadata.pack.inline`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct InlineFile {
+    /// destination path
+    pub dest: String,
+    /// content of the file. `{name}`, `{version}` and `{target}` are replaced with those of the package
+    pub content: String,
+    /// permission bits, an octal string like `"755"`. Defaults to `644`
+    pub mode: Option<Mode>,
+}
+
+/// layout of the destinations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// destinations as they are
+    Flat,
+    /// mirrors an install prefix following the Filesystem Hierarchy Standard.
+    ///
+    /// - binaries go to `bin/`
+    /// - man pages, files named like `*.1` through `*.9`, go to `share/man/man<section>/`
+    /// - other files go to `share/<package name>/`
+    ///
+    /// `Rename` entries are not routed, their `to` is used as is.
+    Fhs,
+}
+
+impl Layout {
+    /// returns the destination of the file whose destination is `dest` in the flat layout
+    pub fn route(&self, package_name: &str, dest: &Path) -> PathBuf {
+        match *self {
+            Layout::Flat => dest.to_path_buf(),
+            Layout::Fhs => {
+                match (man_section(dest), dest.file_name()) {
+                    (Some(section), Some(name)) => Path::new("share/man")
+                        .join(format!("man{}", section))
+                        .join(name),
+                    _ => Path::new("share").join(package_name).join(dest),
                 }
             }
-            Value::Array(mut v) => {
-                match key.parse::<usize>().ok() {
-                    // NOTICE: may change the index
-                    Some(idx) if idx < v.len() => value = v.remove(idx),
-                    _ => return None,
+        }
+    }
+
+    /// returns the destination of the binary `name`
+    pub fn route_binary(&self, name: &OsStr) -> PathBuf {
+        match *self {
+            Layout::Flat => PathBuf::from(name),
+            Layout::Fhs => Path::new("bin").join(name),
+        }
+    }
+}
+
+/// returns the section of the man page `path`, the extension `1` through `9`, if it is one
+fn man_section(path: &Path) -> Option<&str> {
+    path.extension().and_then(|ext| ext.to_str()).and_then(|ext| {
+        if ext.len() == 1 && "123456789".contains(ext) {
+            Some(ext)
+        } else {
+            None
+        }
+    })
+}
+
+/// returns whether `dest` is a man page in `share/man/man*/`
+fn is_installed_man_page(dest: &Path) -> bool {
+    let names = dest.components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.len() >= 4 && names[0] == "share" && names[1] == "man" && names[2].starts_with("man")
+        && man_section(dest).is_some()
+}
+
+impl<'de> Deserialize<'de> for Layout {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "flat" => Ok(Layout::Flat),
+            "fhs" => Ok(Layout::Fhs),
+            _ => Err(de::Error::custom(format!("unknown layout `{}`, expected flat or fhs", s))),
+        }
+    }
+}
+
+/// what to do with destinations differing only by case
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseCollisions {
+    /// fails resolving the package
+    Error,
+    /// logs a warning and packs them
+    Warn,
+}
+
+impl<'de> Deserialize<'de> for CaseCollisions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "error" => Ok(CaseCollisions::Error),
+            "warn" => Ok(CaseCollisions::Warn),
+            _ => Err(de::Error::custom(format!("unknown case-collisions `{}`, expected error or warn", s))),
+        }
+    }
+}
+
+/// keys of `PackConfig` in the manifest, for `CargoPackBuilder::with_strict`
+const PACK_CONFIG_KEYS: &[&str] = &[
+    "files",
+    "default-packers",
+    "warn-file-size",
+    "max-total-size",
+    "format",
+    "compression-level",
+    "store-extensions",
+    "materialize-license",
+    "include-license",
+    "include-lockfile",
+    "include-build-info",
+    "layout",
+    "case-collisions",
+    "bins",
+    "rename-binaries",
+    "check-binary-collisions",
+    "git-tracked-only",
+    "artifact-root",
+    "exclude-extensions",
+    "exclude-larger-than",
+    "gzip-man-pages",
+    "inline",
+    "download-url-template",
+    "artifact-dir",
+    "include-checksums",
+    "os",
+    "target",
+];
+
+impl PackConfig {
+    /// checks that the section `name` has only keys of `PackConfig`. The error names each
+    /// unknown key with a known key close to it, if any, and lists the known keys.
+    fn check_keys(name: &str, section: &Value) -> Result<()> {
+        let table = match section.as_table() {
+            Some(table) => table,
+            None => return Ok(()),
+        };
+        let mut problems = Vec::new();
+        for key in table.keys() {
+            if PACK_CONFIG_KEYS.contains(&key.as_str()) {
+                continue;
+            }
+            match suggest(key, PACK_CONFIG_KEYS) {
+                Some(k) => problems.push(format!("unknown key `{}`. did you mean `{}`?", key, k)),
+                None => problems.push(format!("unknown key `{}`", key)),
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "invalid {}:\n  {}\nknown keys are {}",
+                name,
+                problems.join("\n  "),
+                PACK_CONFIG_KEYS.join(", ")
+            ).into())
+        }
+    }
+
+    /// replaces the fields with those set in `other`. Lists like `files` are replaced as a whole.
+    pub fn override_with(&mut self, other: PackConfig) {
+        if other.files.is_some() {
+            self.files = other.files;
+        }
+        if other.default_packers.is_some() {
+            self.default_packers = other.default_packers;
+        }
+        if other.warn_file_size.is_some() {
+            self.warn_file_size = other.warn_file_size;
+        }
+        if other.max_total_size.is_some() {
+            self.max_total_size = other.max_total_size;
+        }
+        if other.format.is_some() {
+            self.format = other.format;
+        }
+        if other.compression_level.is_some() {
+            self.compression_level = other.compression_level;
+        }
+        if other.store_extensions.is_some() {
+            self.store_extensions = other.store_extensions;
+        }
+        if other.materialize_license.is_some() {
+            self.materialize_license = other.materialize_license;
+        }
+        if other.include_license.is_some() {
+            self.include_license = other.include_license;
+        }
+        if other.include_lockfile.is_some() {
+            self.include_lockfile = other.include_lockfile;
+        }
+        if other.include_build_info.is_some() {
+            self.include_build_info = other.include_build_info;
+        }
+        if other.layout.is_some() {
+            self.layout = other.layout;
+        }
+        if other.case_collisions.is_some() {
+            self.case_collisions = other.case_collisions;
+        }
+        if other.bins.is_some() {
+            self.bins = other.bins;
+        }
+        if other.rename_binaries.is_some() {
+            self.rename_binaries = other.rename_binaries;
+        }
+        if other.check_binary_collisions.is_some() {
+            self.check_binary_collisions = other.check_binary_collisions;
+        }
+        if other.git_tracked_only.is_some() {
+            self.git_tracked_only = other.git_tracked_only;
+        }
+        if other.artifact_root.is_some() {
+            self.artifact_root = other.artifact_root;
+        }
+        if other.exclude_extensions.is_some() {
+            self.exclude_extensions = other.exclude_extensions;
+        }
+        if other.exclude_larger_than.is_some() {
+            self.exclude_larger_than = other.exclude_larger_than;
+        }
+        if other.gzip_man_pages.is_some() {
+            self.gzip_man_pages = other.gzip_man_pages;
+        }
+        if other.inline.is_some() {
+            self.inline = other.inline;
+        }
+        if other.download_url_template.is_some() {
+            self.download_url_template = other.download_url_template;
+        }
+        if other.artifact_dir.is_some() {
+            self.artifact_dir = other.artifact_dir;
+        }
+        if other.include_checksums.is_some() {
+            self.include_checksums = other.include_checksums;
+        }
+        if other.os.is_some() {
+            self.os = other.os;
+        }
+        if other.target.is_some() {
+            self.target = other.target;
+        }
+    }
+
+    /// merges `overlay` into `base`: `files` of both are concatenated, `base` first, keeping only
+    /// the first of the same entries. The other fields, including the other lists, are those of
+    /// `overlay` when set and those of `base` otherwise, as `override_with` does.
+    pub fn merge(base: PackConfig, overlay: PackConfig) -> PackConfig {
+        let files = match (base.files.clone(), overlay.files.clone()) {
+            (Some(base_files), Some(overlay_files)) => {
+                let mut files = Vec::new();
+                for entry in base_files.into_iter().chain(overlay_files) {
+                    if !files.contains(&entry) {
+                        files.push(entry);
+                    }
                 }
+                Some(files)
             }
-            _ => return None,
+            (files, None) | (None, files) => files,
+        };
+        let mut merged = base;
+        merged.override_with(overlay);
+        merged.files = files;
+        merged
+    }
+
+    /// appends `files` of the section of the host OS to `files`, if any
+    fn merge_host_os(&mut self) {
+        let os_files = self.os
+            .as_ref()
+            .and_then(|os| os.get(env::consts::OS))
+            .and_then(|os| os.files.clone());
+        if let Some(os_files) = os_files {
+            debug!("adding files for {}: {:?}", env::consts::OS, os_files);
+            self.add_files(os_files);
         }
     }
 
-    Some(value)
+    /// appends `files` to `files`, leaving `files` as it is when there are none
+    fn add_files(&mut self, files: Vec<FileEntry>) {
+        if !files.is_empty() {
+            self.files.get_or_insert_with(Vec::new).extend(files);
+        }
+    }
+
+    /// checks that each of `default-packers` is one of `known`, the packers the frontend has.
+    /// The error suggests a known packer close to an unknown one, if any.
+    pub fn validate_packers(&self, known: &[&str]) -> Result<()> {
+        let mut problems = Vec::new();
+        for packer in self.default_packers.iter().flat_map(|p| p) {
+            if known.contains(&packer.as_str()) {
+                continue;
+            }
+            match suggest(packer, known) {
+                Some(k) => problems.push(format!("unknown packer `{}`. did you mean `{}`?", packer, k)),
+                None => problems.push(format!("unknown packer `{}`", packer)),
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "invalid package.metadata.pack.default-packers:\n  {}\n  known packers are {}",
+                problems.join("\n  "),
+                known.join(", ")
+            ).into())
+        }
+    }
+
+    /// checks the whole configuration against the package at `package_root`.
+    ///
+    /// Unlike the other methods, this does not stop at the first problem.
+    /// The error lists every problem found, each naming the field and the value.
+    pub fn validate(&self, package_root: &Path) -> Result<()> {
+        let mut problems = Vec::new();
+        let mut packers: Vec<&str> = Vec::new();
+        for packer in self.default_packers.iter().flat_map(|p| p) {
+            if packer.trim().is_empty() {
+                problems.push("default-packers: empty packer name".to_string());
+            } else if packers.contains(&packer.as_str()) {
+                problems.push(format!("default-packers: `{}` is listed more than once", packer));
+            }
+            packers.push(packer);
+        }
+        let files: &[FileEntry] = self.files.as_ref().map(|files| files.as_slice()).unwrap_or(&[]);
+        let extensions = self.exclude_extensions
+            .iter()
+            .flat_map(|e| e)
+            .map(|e| e.trim_left_matches('.').to_lowercase())
+            .collect::<Vec<_>>();
+        let mut renamed: HashMap<&str, &str> = HashMap::new();
+        for (index, entry) in files.iter().enumerate() {
+            if let FileEntry::Rename { ref from, ref to, .. } = *entry {
+                if let Some(other) = renamed.insert(to, from) {
+                    problems.push(format!("files: `{}` is the destination of both `{}` and `{}`", to, other, from));
+                }
+            }
+            if entry.pattern().is_empty() {
+                problems.push("files: empty pattern".to_string());
+                continue;
+            }
+            if let Some(negated) = entry.negated() {
+                if negated.is_empty() {
+                    problems.push("files: empty pattern".to_string());
+                } else if let Err(e) = negated_patterns(Path::new(""), negated) {
+                    problems.push(format!("files: `{}`: {}", entry.pattern(), e));
+                }
+                continue;
+            }
+            // other members, named bases and the target are not known without the workspace
+            let named_base = entry
+                .base()
+                .map_or(false, |base| [OUT_DIR_BASE, PACKAGE_BASE, WORKSPACE_BASE, ARTIFACT_ROOT_BASE].contains(&base));
+            let conditional = match *entry {
+                FileEntry::Conditional { .. } => true,
+                _ => false,
+            };
+            if entry.member().is_some() || named_base || conditional {
+                continue;
+            }
+            let expanded = match entry.expand(package_root) {
+                Ok(expanded) => expanded,
+                Err(e) => {
+                    problems.push(format!("files: `{}`: {}", entry.pattern(), e));
+                    continue;
+                }
+            };
+            let entry_root = match *entry {
+                FileEntry::Based { ref base, .. } => package_root.join(base),
+                _ => package_root.to_path_buf(),
+            };
+            let mut kept = expanded.clone();
+            if subtract_negated(files, index, &entry_root, &match_options(false), &mut kept).is_ok()
+                && kept.is_empty() && !expanded.is_empty()
+            {
+                let removing = files[index + 1..]
+                    .iter()
+                    .filter(|later| later.negated().is_some())
+                    .filter(|later| {
+                        let mut kept = expanded.clone();
+                        let pair = [entry.clone(), (*later).clone()];
+                        subtract_negated(&pair, 0, &entry_root, &match_options(false), &mut kept).is_ok()
+                            && kept.len() < expanded.len()
+                    })
+                    .map(|later| later.pattern())
+                    .collect::<Vec<_>>();
+                problems.push(format!(
+                    "files: `{}` removes every file of `{}`",
+                    removing.join("`, `"),
+                    entry.pattern()
+                ));
+            }
+            // only files listed by name, as globs are expected to match some excluded files
+            if entry.is_glob() {
+                continue;
+            }
+            for e in &expanded {
+                if let EntrySource::Path(ref src) = e.source {
+                    let extension = src.extension().map(|e| e.to_string_lossy().to_lowercase());
+                    if let Some(extension) = extension {
+                        if !extensions.contains(&extension) {
+                            continue;
+                        }
+                        problems.push(format!(
+                            "exclude-extensions: `{}` drops `{}` listed in files",
+                            extension,
+                            entry.pattern()
+                        ));
+                    }
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("invalid package.metadata.pack:\n  {}", problems.join("\n  ")).into())
+        }
+    }
+
+    /// checks that the `to` of each rename entry, in `files` and in the `os` and `target` sections,
+    /// and the `dest` of each inline file are relative paths inside the archive
+    fn check_destinations(&self) -> Result<()> {
+        fn rename(entry: &FileEntry) -> Option<(&str, &str)> {
+            match *entry {
+                FileEntry::Rename { ref from, ref to, .. } => Some((to, from)),
+                FileEntry::Conditional { ref entry, .. } => rename(entry),
+                _ => None,
+            }
+        }
+        let sections = self.os.iter().chain(self.target.iter()).flat_map(|sections| sections.values());
+        let dests = self.files
+            .iter()
+            .chain(sections.filter_map(|section| section.files.as_ref()))
+            .flat_map(|files| files)
+            .filter_map(rename);
+        for (to, from) in dests {
+            if !is_inside(Path::new(to)) {
+                return Err(format!(
+                    "invalid package.metadata.pack.files: the destination `{}` of `{}` must be a relative path \
+                     without `..`",
+                    to, from
+                ).into());
+            }
+        }
+        for file in self.inline.iter().flat_map(|inline| inline) {
+            if !is_inside(Path::new(&file.dest)) {
+                return Err(format!(
+                    "invalid package.metadata.pack.inline: the destination `{}` must be a relative path without `..`",
+                    file.dest
+                ).into());
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<'cfg> CargoPack<'cfg> {
-    /// create a new CargoPack value
+/// returns whether `dest` is a relative path which stays inside the directory it is placed in:
+/// no root, no prefix and no `..`
+pub(crate) fn is_inside(dest: &Path) -> bool {
+    dest.components().all(|c| match c {
+        Component::Prefix(_) | Component::RootDir | Component::ParentDir => false,
+        Component::CurDir | Component::Normal(_) => true,
+    })
+}
+
+/// fails if `path`, matched by an entry, is outside of `real_base`, the canonical directory the
+/// entry is matched in, once symlinks and `..` are resolved
+fn check_inside(path: &Path, real_base: &Path) -> Result<()> {
+    let real = path.canonicalize()
+        .chain_err(|| format!("failed to resolve {}", path.display()))?;
+    if real.starts_with(real_base) {
+        Ok(())
+    } else {
+        Err(format!("{} is outside of {}", path.display(), real_base.display()).into())
+    }
+}
+
+/// an entry of `package.metadata.pack.files`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum FileEntry {
+    /// a glob pattern relative to the package root, like `"README.md"`.
+    /// The destination is the matched path relative to the package root.
     ///
-    /// ```rust
-    /// let config = Config::default().unwrap();
-    /// let pack = CargoPack::new(&config, None);
-    /// ```
+    /// A pattern prefixed with `!`, like `"!logs/**/*.tmp"`, removes the files it matches from those
+    /// of the preceding entries. It is matched relative to the directory of each of them, the
+    /// package root or their `base`, and `*` does not match `/` as in `.gitignore`: `"!logs/*.tmp"`
+    /// keeps `logs/old/a.tmp`. A leading `\!` stands for a literal `!`.
+    ///
+    /// `{a,b}` alternations are expanded as in shells before matching, e.g. `"dist/{js,css}/**"`.
+    /// `\{` stands for a literal `{`.
+    ///
+    /// `**` as a whole path component matches zero or more directories, so `"assets/**/*.png"`
+    /// matches `assets/logo.png` as well as `assets/icons/app/logo.png`. Negated patterns match
+    /// in the same way.
+    Pattern(String),
+    /// another entry packed only for the targets matching `cfg`, like
+    /// `{ glob = "*.so", base = "lib", cfg = "cfg(all(target_os = \"linux\", target_arch = \"x86_64\"))" }`.
+    ///
+    /// The expression is written as the keys of `target` are and checked when the config is
+    /// loaded. When the package is selected, the entry is replaced with the entry without `cfg`
+    /// if the expression matches the target, or removed. This variant comes before the others
+    /// as they would ignore `cfg`.
+    Conditional {
+        /// cfg expression
+        cfg: String,
+        /// the entry without `cfg`
+        #[serde(flatten)]
+        entry: Box<FileEntry>,
+    },
+    /// a glob pattern relative to `base`, like `{ glob = "*.so", base = "target/release" }`.
+    /// `base` is relative to the package root and the destination is the matched path relative to
+    /// `base`.
+    ///
+    /// These names of `base` stand for directories instead of paths. They start with `@` so that
+    /// they cannot be taken for directories of the package named the same, like `out-dir`:
+    ///
+    /// - `@package`: the package root
+    /// - `@workspace`: the workspace root
+    /// - `@artifact-root`: the directory given by `CargoPackBuilder::with_artifact_root`
+    ///   or `artifact-root` of the config
+    /// - `@out-dir`: `OUT_DIR` of the build script
+    Based {
+        /// glob pattern
+        glob: String,
+        /// directory the pattern and the destination are relative to
+        base: String,
+    },
+    /// a file packed under another name, like `{ from = "scripts/run.sh", to = "bin/run" }`.
+    /// `from` is relative to the package root. `mode`, an octal string like `"755"`, overrides the
+    /// permission of the source file.
+    Rename {
+        /// path of the source file
+        from: String,
+        /// destination path, relative and without `..`. `{name}`, `{version}` and `{target}` are replaced
+        /// with those of the package
+        to: String,
+        /// permission bits of the destination
+        mode: Option<Mode>,
+    },
+    /// a file or a directory which can be outside of the package but not of the workspace, like
+    /// `{ from = "../plugins/foo/assets", base = "@workspace" }` for assets of a directory which is
+    /// not a member. `from` is relative to the package root and the destinations are the paths
+    /// relative to the workspace root. Only `@workspace` is supported as `base`.
+    WorkspacePath {
+        /// path of the file or the directory
+        from: String,
+        /// `@workspace`
+        base: String,
+    },
+    /// files of another workspace member, like `{ package = "frontend", files = ["dist/**"] }`.
+    /// Patterns and destinations are relative to the root of the member.
+    MemberFiles {
+        /// name of the workspace member
+        package: String,
+        /// glob patterns relative to the member
+        files: Vec<String>,
+    },
+    /// artifacts of another workspace member, like `{ package = "frontend", artifact = "bin" }`.
+    /// Only `"bin"`, the binaries of the member built with `default_profile()`, is supported.
+    MemberArtifact {
+        /// name of the workspace member
+        package: String,
+        /// kind of the artifact
+        artifact: String,
+    },
+    /// every file in a directory, like `{ dir = "Docs", dest-transform = "lowercase" }`.
+    /// `dir` is relative to the package root and so are the destinations, before they are passed
+    /// through `dest-transform`: a transform or a list of transforms applied in order.
+    Dir {
+        /// path of the directory
+        dir: String,
+        /// transforms of the destinations
+        #[serde(rename = "dest-transform", default, deserialize_with = "deserialize_dest_transforms")]
+        dest_transform: Vec<DestTransform>,
+    },
+}
 
-    pub fn new<'a, P: Into<Option<String>>>(config: &'cfg Config, package_name: P) -> Result<Self> {
-        let package_name = package_name.into();
-        let root = find_root_manifest_for_wd(None, config.cwd())?;
-        let ws: Workspace<'cfg> = Workspace::new(&root, config)?;
-        let pack_config: PackConfig =
-            Self::decode_from_manifest_static(&ws, package_name.as_ref().map(|s| s.as_ref()))?;
-        debug!("config: {:?}", pack_config);
-        Ok(CargoPack {
-            ws: ws,
-            pack_config: pack_config,
-            package_name: package_name,
-        })
+/// octal permission bits like `"755"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode(pub u32);
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match u32::from_str_radix(&s, 8) {
+            Ok(mode) if mode <= 0o7777 => Ok(Mode(mode)),
+            _ => Err(de::Error::custom(format!("invalid mode `{}`, expected octal like \"755\"", s))),
+        }
     }
+}
 
-    /// returns the current working space of the package of `package_name`
-    pub fn ws(&self) -> &Workspace<'cfg> {
-        &self.ws
+/// a size in bytes, written as a number of bytes or a string with a unit like `"50MB"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Size(pub u64);
+
+/// units of `Size` and their bytes, compared case-insensitively
+const SIZE_UNITS: &[(&str, u64)] = &[
+    ("b", 1),
+    ("kb", 1_000),
+    ("mb", 1_000_000),
+    ("gb", 1_000_000_000),
+    ("kib", 1 << 10),
+    ("mib", 1 << 20),
+    ("gib", 1 << 30),
+];
+
+impl FromStr for Size {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_digit(10)).unwrap_or(s.len());
+        let (number, unit) = (&s[..split], s[split..].trim().to_lowercase());
+        let invalid = || Error::from(format!("invalid size `{}`, expected bytes or a size like \"50MB\"", s));
+        let number = number.parse::<u64>().map_err(|_| invalid())?;
+        let bytes = if unit.is_empty() {
+            1
+        } else {
+            SIZE_UNITS.iter().find(|&&(name, _)| name == unit).ok_or_else(&invalid)?.1
+        };
+        Ok(Size(number.checked_mul(bytes).ok_or_else(&invalid)?))
     }
+}
 
-    /// returns the PackConfig value
-    pub fn config(&self) -> &PackConfig {
-        &self.pack_config
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        struct SizeVisitor;
+
+        impl<'de> de::Visitor<'de> for SizeVisitor {
+            type Value = Size;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a size in bytes or a string like \"50MB\"")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> ::std::result::Result<Size, E> {
+                Ok(Size(v))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> ::std::result::Result<Size, E> {
+                if v < 0 {
+                    return Err(E::custom(format!("invalid size {}, expected a positive number", v)));
+                }
+                Ok(Size(v as u64))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> ::std::result::Result<Size, E> {
+                v.parse().map_err(|e: Error| E::custom(e.to_string()))
+            }
+        }
+
+        deserializer.deserialize_any(SizeVisitor)
     }
+}
 
-    /// returns the `Package` value of `package_name`
-    pub fn package(&self) -> Result<&Package> {
-        if let Some(ref name) = self.package_name {
-            let packages = self.ws()
-                .members()
-                .filter(|p| p.package_id().name() == *name)
-                .collect::<Vec<_>>();
-            match packages.len() {
-                0 => return Err(format!("unknown package {}", name).into()),
-                1 => Ok(packages[0]),
-                _ => return Err(format!("ambiguous name {}", name).into()),
+/// a transformation of the destinations of a `Dir` entry, written as a string:
+///
+/// - `"lowercase"`: lowercases the destination
+/// - `"prefix:<prefix>"`: prepends `<prefix>`, e.g. `"prefix:doc/"`
+/// - `"strip-prefix:<prefix>"`: removes `<prefix>`, e.g. `"strip-prefix:Docs/"`. It is an error
+///   for a destination not to start with it or to be the prefix itself.
+///
+/// Prefixes are added and removed as text, so `"prefix:doc-"` makes `README.md` `doc-README.md`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestTransform {
+    /// `"lowercase"`
+    Lowercase,
+    /// `"prefix:<prefix>"`
+    Prefix(String),
+    /// `"strip-prefix:<prefix>"`
+    StripPrefix(String),
+}
+
+impl DestTransform {
+    /// returns `dest` transformed
+    pub fn apply(&self, dest: &Path) -> Result<PathBuf> {
+        let dest_str = dest.to_string_lossy();
+        match *self {
+            DestTransform::Lowercase => Ok(PathBuf::from(dest_str.to_lowercase())),
+            DestTransform::Prefix(ref prefix) => Ok(PathBuf::from(format!("{}{}", prefix, dest_str))),
+            DestTransform::StripPrefix(ref prefix) => {
+                if dest_str.len() > prefix.len() && dest_str.starts_with(prefix.as_str()) {
+                    Ok(PathBuf::from(&dest_str[prefix.len()..]))
+                } else {
+                    Err(format!("{} does not start with `{}` to strip", dest.display(), prefix).into())
+                }
             }
+        }
+    }
+}
+
+impl FromStr for DestTransform {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "lowercase" {
+            Ok(DestTransform::Lowercase)
+        } else if s.starts_with("strip-prefix:") {
+            Ok(DestTransform::StripPrefix(s["strip-prefix:".len()..].to_string()))
+        } else if s.starts_with("prefix:") {
+            Ok(DestTransform::Prefix(s["prefix:".len()..].to_string()))
         } else {
-            Ok(self.ws().current()?)
+            Err(format!(
+                "unknown dest-transform `{}`, expected lowercase, prefix:<prefix> or strip-prefix:<prefix>",
+                s
+            ).into())
         }
     }
+}
 
-    fn decode_from_manifest_static<T: DeserializeOwned>(
-        ws: &Workspace,
-        package_name: Option<&str>,
-    ) -> Result<T> {
-        let manifest = if let Some(ref name) = package_name {
-            let names = ws.members()
-                .filter(|p| p.package_id().name() == *name)
-                .collect::<Vec<_>>();
-            match names.len() {
-                0 => return Err(format!("unknown package {}", name).into()),
-                1 => names[0].manifest_path(),
-                _ => return Err(format!("ambiguous name {}", name).into()),
+/// deserializes `dest-transform`, a transform or a list of them
+fn deserialize_dest_transforms<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> ::std::result::Result<Vec<DestTransform>, D::Error> {
+    struct TransformsVisitor;
+
+    impl<'de> de::Visitor<'de> for TransformsVisitor {
+        type Value = Vec<DestTransform>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a transform like \"lowercase\" or a list of them")
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> ::std::result::Result<Self::Value, E> {
+            v.parse()
+                .map(|t| vec![t])
+                .map_err(|e: Error| E::custom(e.to_string()))
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> ::std::result::Result<Self::Value, A::Error> {
+            let mut transforms = Vec::new();
+            while let Some(s) = seq.next_element::<String>()? {
+                transforms.push(s.parse().map_err(|e: Error| de::Error::custom(e.to_string()))?);
             }
-        } else {
-            ws.current()?.manifest_path()
-        };
-        debug!("reading manifest: {:?}", manifest);
+            Ok(transforms)
+        }
+    }
 
-        let contents = paths::read(manifest)?;
-        let root = toml::parse(&contents, &manifest, ws.config())?;
-        debug!("root: {:?}", root);
-        let data = lookup(root, &["package", "metadata", "pack"])
-            .expect("no package.metadata.pack found in Cargo.toml");
-        data.try_into().map_err(Into::into)
+    deserializer.deserialize_any(TransformsVisitor)
+}
+
+impl FileEntry {
+    /// returns the glob pattern of the entry, the source path for `Rename`, the directory for
+    /// `Dir` or the package name for entries referring to another member
+    pub fn pattern(&self) -> &str {
+        match *self {
+            FileEntry::Pattern(ref pattern) => pattern,
+            FileEntry::Conditional { ref entry, .. } => entry.pattern(),
+            FileEntry::Based { ref glob, .. } => glob,
+            FileEntry::Rename { ref from, .. } | FileEntry::WorkspacePath { ref from, .. } => from,
+            FileEntry::Dir { ref dir, .. } => dir,
+            FileEntry::MemberFiles { ref package, .. } |
+            FileEntry::MemberArtifact { ref package, .. } => package,
+        }
     }
 
-    /// decode a value from the manifest toml file.
-    pub fn decode_from_manifest<'a, T: DeserializeOwned>(&self) -> Result<T> {
-        let package_name = self.package_name.as_ref().map(|s| s.as_ref());
-        Self::decode_from_manifest_static(self.ws(), package_name)
+    /// returns the workspace member the entry refers to, if any
+    pub fn member(&self) -> Option<&str> {
+        match *self {
+            FileEntry::MemberFiles { ref package, .. } |
+            FileEntry::MemberArtifact { ref package, .. } => Some(package),
+            _ => None,
+        }
     }
 
-    /// returns files defined in `package.metadata.pack.files` in the Cargo.toml.
-    pub fn files(&self) -> &[String] {
-        self.pack_config
-            .files
-            .as_ref()
-            .map(AsRef::as_ref)
-            .unwrap_or(&[])
+    /// returns whether the entry has a glob pattern, rather than only file names
+    pub fn is_glob(&self) -> bool {
+        match *self {
+            FileEntry::Pattern(ref pattern) => is_file_pattern(pattern),
+            FileEntry::Conditional { ref entry, .. } => entry.is_glob(),
+            FileEntry::Based { ref glob, .. } => is_file_pattern(glob),
+            FileEntry::MemberFiles { ref files, .. } => files.iter().any(|f| is_file_pattern(f)),
+            FileEntry::Dir { .. } => true,
+            FileEntry::Rename { .. } | FileEntry::WorkspacePath { .. } | FileEntry::MemberArtifact { .. } => false,
+        }
+    }
+
+    /// returns the base directory of the entry, if any
+    pub fn base(&self) -> Option<&str> {
+        match *self {
+            FileEntry::Based { ref base, .. } | FileEntry::WorkspacePath { ref base, .. } => Some(base),
+            _ => None,
+        }
+    }
+
+    /// returns the pattern without `!` if the entry is a negated pattern
+    pub fn negated(&self) -> Option<&str> {
+        match *self {
+            FileEntry::Pattern(ref pattern) if pattern.starts_with('!') => Some(&pattern[1..]),
+            _ => None,
+        }
+    }
+
+    /// expands the entry into entries to pack under the package `root`.
+    /// It is an error for the entry to match no file, or a file outside of the directory it is
+    /// matched in once symlinks and `..` are resolved: the base, or `root` for `Dir` entries.
+    /// Wildcards do not match names starting with `.`, as `CargoPack` does by default.
+    /// Entries referring to other members are expanded by `CargoPack`.
+    fn expand(&self, root: &Path) -> Result<Vec<PackEntry>> {
+        self.expand_with(root, &match_options(false))
+    }
+
+    /// expands the entry as `expand` does, matching the patterns with `options`
+    fn expand_with(&self, root: &Path, options: &glob::MatchOptions) -> Result<Vec<PackEntry>> {
+        if let FileEntry::Conditional { ref cfg, .. } = *self {
+            return Err(format!("files entry with {} can only be resolved for a target", cfg).into());
+        }
+        if let Some(member) = self.member() {
+            return Err(format!("files of {} can only be resolved within the workspace", member).into());
+        }
+        if let FileEntry::WorkspacePath { ref from, .. } = *self {
+            return Err(format!("{} can only be resolved within the workspace", from).into());
+        }
+        if let FileEntry::Rename { ref from, ref to, mode } = *self {
+            let src = root.join(from);
+            if !src.is_file() {
+                return Err(format!("{} is not a file", src.display()).into());
+            }
+            return Ok(vec![
+                PackEntry {
+                    source: EntrySource::Path(src),
+                    dest: PathBuf::from(to),
+                    mode: mode.map(|m| m.0),
+                },
+            ]);
+        }
+        if let FileEntry::Dir {
+            ref dir,
+            ref dest_transform,
+        } = *self
+        {
+            let dir_path = root.join(dir);
+            if !dir_path.is_dir() {
+                return Err(format!("{} is not a directory", dir_path.display()).into());
+            }
+            let dir_str = dir_path
+                .to_str()
+                .ok_or_else(|| format!("non UTF-8 path {:?}", dir_path))?;
+            let real_root = root.canonicalize()
+                .chain_err(|| format!("failed to resolve {}", root.display()))?;
+            let mut entries = Vec::new();
+            for path in glob::glob_with(&format!("{}/**/*", glob::Pattern::escape(dir_str)), options)? {
+                let path = path?;
+                if !path.is_file() {
+                    continue;
+                }
+                check_inside(&path, &real_root)?;
+                let mut dest = path.strip_prefix(root)
+                    .map_err(|_| format!("{:?} is outside of {:?}", path, root))?
+                    .to_path_buf();
+                for transform in dest_transform {
+                    dest = transform.apply(&dest)?;
+                }
+                entries.push(PackEntry {
+                    source: EntrySource::Path(path),
+                    dest: dest,
+                    mode: None,
+                });
+            }
+            if entries.is_empty() {
+                return Err(format!("no files in {}", dir_path.display()).into());
+            }
+            return Ok(entries);
+        }
+        let base = match self.base() {
+            Some(base) => root.join(base),
+            None => root.to_path_buf(),
+        };
+        let pattern = match *self {
+            FileEntry::Pattern(ref pattern) if pattern.starts_with("\\!") => &pattern[1..],
+            _ => self.pattern(),
+        };
+        let mut expanded = Vec::new();
+        let mut seen = HashSet::new();
+        for alternative in brace::expand(pattern)? {
+            let pattern_path = base.join(alternative);
+            let pattern_str = pattern_path
+                .to_str()
+                .ok_or_else(|| format!("non UTF-8 path {:?}", pattern_path))?;
+            for path in glob::glob_with(pattern_str, options)? {
+                let path = path?;
+                if path.is_file() && seen.insert(path.clone()) {
+                    expanded.push(path);
+                }
+            }
+        }
+        let mut entries = Vec::new();
+        let real_base = match expanded.first() {
+            Some(_) => base.canonicalize()
+                .chain_err(|| format!("failed to resolve {}", base.display()))?,
+            None => base.clone(),
+        };
+        for path in expanded {
+            check_inside(&path, &real_base)?;
+            let dest = path.strip_prefix(&base)
+                .map_err(|_| format!("{:?} is outside of {:?}", path, base))?
+                .to_path_buf();
+            entries.push(PackEntry {
+                source: EntrySource::Path(path),
+                dest: dest,
+                mode: None,
+            });
+        }
+        if entries.is_empty() {
+            return Err(format!("no files matched {}", base.join(pattern).display()).into());
+        }
+        Ok(entries)
+    }
+}
+
+/// where the content of a `PackEntry` comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntrySource {
+    /// a file on the disk
+    Path(PathBuf),
+    /// content generated in memory
+    Content(Vec<u8>),
+}
+
+/// a file to pack
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackEntry {
+    /// where the content comes from
+    pub source: EntrySource,
+    /// relative path in the package
+    pub dest: PathBuf,
+    /// permission bits to use instead of the source's
+    pub mode: Option<u32>,
+}
+
+/// cargo-pack API
+pub struct CargoPack<'cfg> {
+    ws: Workspace<'cfg>,
+    package_name: Option<String>,
+    pack_config: PackConfig,
+    target: Option<String>,
+    target_dir: Option<PathBuf>,
+    lock_mode: Option<LockMode>,
+    metadata_path: Vec<String>,
+    config_overrides: Option<PackConfig>,
+    content_cache: ContentCache,
+    preserve_xattrs: bool,
+    manifest: Value,
+    universal_macos: bool,
+    transform: Option<Transform<'cfg>>,
+    strict: bool,
+    artifact_root: Option<PathBuf>,
+    output_mtime: OutputMtime,
+    include_hidden: bool,
+    include_binaries: bool,
+    jobs: usize,
+    root_prefix: Option<PathBuf>,
+}
+
+/// transform of the contents given by `CargoPackBuilder::with_transform`
+type Transform<'cfg> = Arc<Fn(&Path, Vec<u8>) -> Result<Vec<u8>> + Send + Sync + 'cfg>;
+
+/// builder of `CargoPack`. Created by `CargoPack::builder`.
+#[derive(Clone)]
+pub struct CargoPackBuilder<'cfg> {
+    config: &'cfg Config,
+    package_name: Option<String>,
+    cwd: Option<PathBuf>,
+    manifest_path: Option<PathBuf>,
+    target: Option<String>,
+    target_dir: Option<PathBuf>,
+    lock_mode: Option<LockMode>,
+    metadata_path: Vec<String>,
+    offline: bool,
+    config_overrides: Option<PackConfig>,
+    content_cache_limit: u64,
+    preserve_xattrs: bool,
+    universal_macos: bool,
+    transform: Option<Transform<'cfg>>,
+    strict: bool,
+    artifact_root: Option<PathBuf>,
+    output_mtime: OutputMtime,
+    include_hidden: bool,
+    include_binaries: bool,
+    jobs: usize,
+    root_prefix: Option<PathBuf>,
+}
+
+impl<'cfg> CargoPackBuilder<'cfg> {
+    /// selects the package to pack. Defaults to the current package of the workspace.
+    ///
+    /// The name can be a glob pattern like `app-*`. `build` fails unless it matches exactly one
+    /// member, and `build_all` creates a value for each matching member. `<name>:<version>`, like
+    /// `app:1.2.3`, selects the member having both the name and the version.
+    pub fn with_package_name<P: Into<Option<String>>>(mut self, package_name: P) -> Self {
+        self.package_name = package_name.into();
+        self
+    }
+
+    /// searches the manifest from `dir` instead of the working directory of the process.
+    ///
+    /// This only affects manifest discovery, so it is ignored when an explicit manifest path
+    /// is given by `with_manifest_path`.
+    pub fn with_cwd(mut self, dir: PathBuf) -> Self {
+        self.cwd = Some(dir);
+        self
+    }
+
+    /// uses `path` as the root manifest instead of searching it.
+    /// A relative path is resolved against the working directory of the process.
+    pub fn with_manifest_path(mut self, path: PathBuf) -> Self {
+        self.manifest_path = Some(path);
+        self
+    }
+
+    /// looks up binaries built for the target `triple`.
+    ///
+    /// This overrides `build.target` of cargo's configuration.
+    pub fn with_target(mut self, triple: String) -> Self {
+        self.target = Some(triple);
+        self
+    }
+
+    /// looks up binaries in `dir` instead of the target directory of the workspace.
+    pub fn with_target_dir(mut self, dir: PathBuf) -> Self {
+        self.target_dir = Some(dir);
+        self
+    }
+
+    /// holds an advisory lock on `.cargo-pack.lock` in the target directory while writing outputs,
+    /// so that concurrent invocations for the same workspace do not interleave.
+    /// `mode` decides whether to wait for or fail on a contended lock.
+    pub fn with_lock(mut self, mode: LockMode) -> Self {
+        self.lock_mode = Some(mode);
+        self
+    }
+
+    /// reads the config from `package.metadata.<namespace>` instead of `package.metadata.pack`,
+    /// so that a tool built on this crate can have a section named after itself.
+    pub fn with_namespace(mut self, namespace: String) -> Self {
+        self.metadata_path = metadata_path_of(&namespace);
+        self
+    }
+
+    /// reads the config from the table at `path` of keys in the manifest instead of
+    /// `package.metadata.pack`, e.g. `["package", "metadata", "mycompany", "pack"]`.
+    /// When the path starts with `package`, the same path under `workspace` is looked up next
+    /// in the root manifest. `build` fails if the path is empty.
+    pub fn with_metadata_path(mut self, path: Vec<String>) -> Self {
+        self.metadata_path = path;
+        self
+    }
+
+    /// requires that no network access happens while creating and using the CargoPack value.
+    ///
+    /// This maps onto cargo's `--frozen`: the `Config` must be configured with `frozen` set by
+    /// `Config::configure`, since it is only borrowed here. `build` fails if it is not,
+    /// and cargo fails with its own error wherever it would need the network.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// overrides the config read from the manifest with the fields set in `config`,
+    /// as `PackConfig::override_with` does. The `package.metadata.pack` section may be absent then,
+    /// so a caller can supply the whole config, e.g. `files` computed from a build graph.
+    pub fn with_config_overrides(mut self, config: PackConfig) -> Self {
+        self.config_overrides = Some(config);
+        self
+    }
+
+    /// keeps the contents of source files read by the archive methods in memory, up to `limit`
+    /// bytes in total, so that writing several archives from the same value reads each file once.
+    /// Files which do not fit are read every time. A cached content is reread when the size or
+    /// the mtime of the file changes.
+    pub fn with_content_cache(mut self, limit: u64) -> Self {
+        self.content_cache_limit = limit;
+        self
+    }
+
+    /// records the extended attributes of source files, like `security.capability`, as pax
+    /// headers in tarballs, which `tar --xattrs` restores. Zip archives do not have them.
+    /// Where they cannot be read, the file is packed without them and a warning is logged.
+    pub fn with_preserve_xattrs(mut self, preserve: bool) -> Self {
+        self.preserve_xattrs = preserve;
+        self
+    }
+
+    /// packs universal macOS binaries instead of binaries for one target.
+    ///
+    /// Each binary must be built for both `x86_64-apple-darwin` and `aarch64-apple-darwin`.
+    /// They are combined with `lipo -create`, which is only available on macOS,
+    /// into `universal-apple-darwin/<profile>/` of the target directory. `with_target` is ignored.
+    pub fn with_universal_macos(mut self, universal: bool) -> Self {
+        self.universal_macos = universal;
+        self
+    }
+
+    /// passes the content of each file to pack through `transform`, e.g. to minify or to fill in
+    /// the version, before it is copied or archived. Checksums are of the transformed contents.
+    ///
+    /// `transform` is called with the destination and the content, and returns the new content.
+    /// Binaries, of the package or of other members, are packed as they are.
+    pub fn with_transform<F>(mut self, transform: F) -> Self
+    where
+        F: Fn(&Path, Vec<u8>) -> Result<Vec<u8>> + Send + Sync + 'cfg,
+    {
+        self.transform = Some(Arc::new(transform));
+        self
+    }
+
+    /// rejects keys of `package.metadata.pack` which are not of `PackConfig`, to catch typos
+    /// like `flies`. Defaults to `false`, since packers may have their own keys in the section.
+    /// Sections given by `with_namespace` or `with_metadata_path` are checked in the same way.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// uses `dir` as the directory of files entries with `base = "@artifact-root"`, e.g. a shared
+    /// output directory of the build system. A relative path is resolved against the package root.
+    /// Takes precedence over `artifact-root` of the config.
+    pub fn with_artifact_root(mut self, dir: PathBuf) -> Self {
+        self.artifact_root = Some(dir);
+        self
+    }
+
+    /// sets the modification time of the archives written, for deployment systems which look at
+    /// it. Entries in the archives keep their normalized mtime. Defaults to `OutputMtime::Keep`.
+    pub fn with_output_mtime(mut self, mtime: OutputMtime) -> Self {
+        self.output_mtime = mtime;
+        self
+    }
+
+    /// whether wildcards of `files` match names starting with `.`, like `config/*` matching
+    /// `config/.env`. Defaults to `false`: hidden files are packed only when named literally,
+    /// like `config/.env`, and `**` does not enter hidden directories such as `.git`.
+    ///
+    /// It only changes what the patterns match: `git-tracked-only` still drops untracked hidden
+    /// files, and files in the target directory are matched either way.
+    pub fn with_include_hidden(mut self, include: bool) -> Self {
+        self.include_hidden = include;
+        self
+    }
+
+    /// whether plans and archives contain the binaries of the package. Defaults to `true`. With
+    /// `false`, only `files` are packed, e.g. for a bundle of assets released apart from the
+    /// binaries. Binaries of other members listed in `files` are still packed.
+    pub fn with_include_binaries(mut self, include: bool) -> Self {
+        self.include_binaries = include;
+        self
+    }
+
+    /// number of threads to use. Defaults to 1.
+    ///
+    /// With more than one, glob patterns of `files` relative to the package or to a directory
+    /// are matched in that many threads. The files resolved are the same as with one thread,
+    /// since the matches are filtered and sorted afterwards as they are with one. Entries
+    /// referring to other members or named bases are always expanded on the calling thread.
+    /// Only matching runs in threads: files are copied and archived on the calling thread.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// reads the absolute paths written in the config under `prefix`, for builds in a chroot-like
+    /// sandbox where `/` of the config is `prefix` on the real file system.
+    ///
+    /// The prefix is applied to reads only: the `from` of renamed files, the `base` of files
+    /// entries and the artifact root when they are absolute. Destinations are relative to the
+    /// directories matched in, so they never contain the prefix. Paths found through cargo, like
+    /// the workspace and the target directory, are already real and are used as they are.
+    pub fn with_root_prefix(mut self, prefix: PathBuf) -> Self {
+        self.root_prefix = Some(prefix);
+        self
+    }
+
+    /// creates a CargoPack value for each member matching the package name pattern,
+    /// in the order of the workspace members. Without a package name, creates one for the
+    /// current package as `build` does.
+    ///
+    /// It is an error if binaries of the members with `check-binary-collisions` are packed at the
+    /// same destination, even though they are of different members.
+    pub fn build_all(self) -> Result<Vec<CargoPack<'cfg>>> {
+        let pattern = match self.package_name.clone() {
+            Some(pattern) => pattern,
+            None => return Ok(vec![self.build()?]),
+        };
+        let names = matching_members(&self.load_workspace()?, &pattern)?
+            .iter()
+            .map(|p| p.name().to_string())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return Err(format!("no package matches {}", pattern).into());
+        }
+        let packs = names
+            .into_iter()
+            .map(|name| self.clone().with_package_name(name).build())
+            .collect::<Result<Vec<_>>>()?;
+        plan::check_batch_binary_collisions(&packs)?;
+        Ok(packs)
+    }
+
+    fn load_workspace(&self) -> Result<Workspace<'cfg>> {
+        let config = self.config;
+        if self.offline && config.network_allowed() {
+            return Err("offline mode requires cargo's configuration to be frozen. \
+                        configure it with frozen set by `Config::configure`"
+                .into());
+        }
+        let root = match self.manifest_path {
+            Some(ref path) => {
+                find_root_manifest_for_wd(Some(path.to_string_lossy().into_owned()), config.cwd())?
+            }
+            None => {
+                let cwd = self.cwd.as_ref().map(AsRef::as_ref).unwrap_or(config.cwd());
+                find_root_manifest_for_wd(None, cwd)?
+            }
+        };
+        Ok(Workspace::new(&root, config)
+            .chain_err(|| format!("failed to load the workspace of {}", root.display()))?)
+    }
+
+    /// creates the CargoPack value
+    pub fn build(self) -> Result<CargoPack<'cfg>> {
+        let ws = self.load_workspace()?;
+        let package_name = self.package_name;
+        let pack_config = CargoPack::load_pack_config(
+            &ws,
+            package_name.as_ref().map(|s| s.as_ref()),
+            &self.metadata_path,
+            self.config_overrides.as_ref(),
+            self.strict,
+        )?;
+        pack_config.check_destinations()?;
+        debug!("config: {:?}", pack_config);
+        let (_, manifest) = CargoPack::read_manifest(&ws, package_name.as_ref().map(|s| s.as_ref()))?;
+        let mut cargo_pack = CargoPack {
+            ws: ws,
+            pack_config: pack_config,
+            package_name: package_name,
+            target: self.target,
+            target_dir: self.target_dir,
+            lock_mode: self.lock_mode,
+            metadata_path: self.metadata_path,
+            config_overrides: self.config_overrides,
+            content_cache: ContentCache::new(self.content_cache_limit),
+            preserve_xattrs: self.preserve_xattrs,
+            manifest: manifest,
+            universal_macos: self.universal_macos,
+            transform: self.transform,
+            strict: self.strict,
+            artifact_root: self.artifact_root,
+            output_mtime: self.output_mtime,
+            include_hidden: self.include_hidden,
+            include_binaries: self.include_binaries,
+            jobs: self.jobs,
+            root_prefix: self.root_prefix,
+        };
+        let mut pack_config = cargo_pack.pack_config.clone();
+        cargo_pack.apply_target_cfgs(&mut pack_config)?;
+        cargo_pack.pack_config = pack_config;
+        Ok(cargo_pack)
+    }
+}
+
+/// same as `lookup` but borrows the value
+fn lookup_ref<'a>(mut value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    for key in path {
+        value = match *value {
+            Value::Table(ref table) => table.get(*key)?,
+            Value::Array(ref array) => array.get(key.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+fn lookup(mut value: Value, path: &[&str]) -> Option<Value> {
+    for key in path {
+        match value {
+            Value::Table(mut hm) => {
+                // removing to take the ownership
+                match hm.remove(*key) {
+                    Some(v) => value = v,
+                    None => return None,
+                }
+            }
+            Value::Array(mut v) => {
+                match key.parse::<usize>().ok() {
+                    // NOTICE: may change the index
+                    Some(idx) if idx < v.len() => value = v.remove(idx),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        }
+    }
+
+    Some(value)
+}
+
+/// returns the closest of `candidates` to the unknown `name` to suggest, if any is close enough
+fn suggest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    candidates
+        .iter()
+        .map(|&candidate| (lev_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance <= 3)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// returns the path of `package.metadata.<namespace>`
+fn metadata_path_of(namespace: &str) -> Vec<String> {
+    vec!["package".to_string(), "metadata".to_string(), namespace.to_string()]
+}
+
+/// `base` of files entries standing for `OUT_DIR` of the build script
+const OUT_DIR_BASE: &str = "@out-dir";
+
+/// `base` of files entries standing for the package root
+const PACKAGE_BASE: &str = "@package";
+
+/// `base` of files entries standing for the workspace root
+const WORKSPACE_BASE: &str = "@workspace";
+
+/// `base` of files entries standing for the artifact root
+const ARTIFACT_ROOT_BASE: &str = "@artifact-root";
+
+/// targets combined into a universal binary by `CargoPackBuilder::with_universal_macos`
+const UNIVERSAL_MACOS_TARGETS: &[&str] = &["x86_64-apple-darwin", "aarch64-apple-darwin"];
+
+/// returns whether `name` is a glob pattern rather than a package name
+fn is_pattern(name: &str) -> bool {
+    name.contains(|c| c == '*' || c == '?' || c == '[')
+}
+
+/// compares `a` and `b` treating runs of digits as numbers, so that `file2` < `file10`.
+/// Numbers equal in value like `01` and `1` are ordered by their length.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().cloned(), b.peek().cloned()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_digit(10) && y.is_digit(10) => {
+                let take_number = |chars: &mut iter::Peekable<str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.peek().cloned() {
+                        if !c.is_digit(10) {
+                            break;
+                        }
+                        digits.push(c);
+                        chars.next();
+                    }
+                    digits
+                };
+                let (x, y) = (take_number(&mut a), take_number(&mut b));
+                let (tx, ty) = (x.trim_left_matches('0'), y.trim_left_matches('0'));
+                let ordering = tx.len()
+                    .cmp(&ty.len())
+                    .then_with(|| tx.cmp(ty))
+                    .then_with(|| x.len().cmp(&y.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// returns whether `name` is a glob pattern of `files`, which may also have `{a,b}` alternations
+fn is_file_pattern(name: &str) -> bool {
+    is_pattern(name) || name.contains('{')
+}
+
+/// drops files excluded by `exclude-extensions` and `exclude-larger-than` of `config`
+fn exclude_by_config(config: &PackConfig, entries: Vec<PackEntry>) -> Result<Vec<PackEntry>> {
+    let extensions = config
+        .exclude_extensions
+        .iter()
+        .flat_map(|e| e)
+        .map(|e| e.trim_left_matches('.').to_lowercase())
+        .collect::<Vec<_>>();
+    let mut kept = Vec::new();
+    for entry in entries {
+        if let EntrySource::Path(ref src) = entry.source {
+            let extension = src.extension().map(|e| e.to_string_lossy().to_lowercase());
+            if extension.map_or(false, |e| extensions.contains(&e)) {
+                debug!("excluded {} by its extension", src.display());
+                continue;
+            }
+            if let Some(Size(limit)) = config.exclude_larger_than {
+                if fs::metadata(src)?.len() > limit {
+                    debug!("excluded {} larger than {} bytes", src.display(), limit);
+                    continue;
+                }
+            }
+        }
+        kept.push(entry);
+    }
+    Ok(kept)
+}
+
+/// returns the options to match glob patterns of `files` with, wildcards matching names starting
+/// with `.` if `include_hidden`
+fn match_options(include_hidden: bool) -> glob::MatchOptions {
+    glob::MatchOptions {
+        require_literal_leading_dot: !include_hidden,
+        ..glob::MatchOptions::new()
+    }
+}
+
+/// drops from `expanded`, the files the `index`th entry of `files` expanded to, those matched by
+/// the negated patterns after it with `options`, rooted at `entry_root`, then unless the entry is
+/// of binaries, those excluded by `exclude-extensions` and `exclude-larger-than` of `config`.
+/// `entry_root` is only called if negated patterns follow.
+fn filter_expanded<F>(
+    config: &PackConfig,
+    files: &[FileEntry],
+    index: usize,
+    options: &glob::MatchOptions,
+    entry_root: F,
+    mut expanded: Vec<PackEntry>,
+) -> Result<Vec<PackEntry>>
+where
+    F: FnOnce() -> Result<PathBuf>,
+{
+    if files[index + 1..].iter().any(|e| e.negated().is_some()) {
+        subtract_negated(files, index, &entry_root()?, options, &mut expanded)?;
+    }
+    match files[index] {
+        FileEntry::MemberArtifact { .. } => Ok(expanded),
+        _ => exclude_by_config(config, expanded),
+    }
+}
+
+/// expands each of `entries`, indices of `files` paired with the entries, under `root` in up to
+/// `jobs` threads. Returns the results by the indices.
+fn expand_in_threads(
+    root: &Path,
+    entries: Vec<(usize, FileEntry)>,
+    options: &glob::MatchOptions,
+    jobs: usize,
+) -> Result<HashMap<usize, Result<Vec<PackEntry>>>> {
+    let mut buckets = (0..jobs).map(|_| Vec::new()).collect::<Vec<_>>();
+    for (i, entry) in entries.into_iter().enumerate() {
+        buckets[i % jobs].push(entry);
+    }
+    let handles = buckets
+        .into_iter()
+        .filter(|bucket| !bucket.is_empty())
+        .map(|bucket| {
+            let root = root.to_path_buf();
+            let options = options.clone();
+            thread::spawn(move || {
+                bucket
+                    .into_iter()
+                    .map(|(index, entry)| (index, entry.expand_with(&root, &options)))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut expanded = HashMap::new();
+    for handle in handles {
+        let results = handle
+            .join()
+            .map_err(|_| Error::from("a thread matching files panicked"))?;
+        expanded.extend(results);
+    }
+    Ok(expanded)
+}
+
+/// sorts resolved entries by the destination and removes duplicates
+fn sort_resolved(resolved: &mut Vec<PackEntry>) {
+    resolved.sort_by(|a, b| a.dest.cmp(&b.dest));
+    resolved.dedup();
+}
+
+/// resolves `files` of `config` under `root`, the package root, into source paths paired with
+/// their destinations, sorted by the destination and deduplicated, for callers without a workspace.
+///
+/// Each entry is expanded, then filtered by the negated patterns after it, `exclude-extensions`
+/// and `exclude-larger-than` as `CargoPack::resolved_files_with_dest` filters it.
+/// It is an error to have entries which need the workspace: those of other members, those
+/// with a named `base` other than `@package` and those with `cfg`, evaluated for the target.
+/// Wildcards do not match hidden files, as with the default of `CargoPackBuilder::with_include_hidden`.
+/// `git-tracked-only`, `os` and the layout are not applied.
+pub fn resolve_files(root: &Path, config: &PackConfig) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let files: &[FileEntry] = config.files.as_ref().map(|files| files.as_slice()).unwrap_or(&[]);
+    let options = match_options(false);
+    let mut resolved = Vec::new();
+    for (index, entry) in files.iter().enumerate() {
+        if entry.negated().is_some() {
+            continue;
+        }
+        let (entry_root, expanded) = match *entry {
+            FileEntry::Based { ref glob, ref base } if base == PACKAGE_BASE => {
+                (root.to_path_buf(), FileEntry::Pattern(glob.clone()).expand_with(root, &options)?)
+            }
+            FileEntry::Based { ref base, .. } | FileEntry::WorkspacePath { ref base, .. }
+                if [OUT_DIR_BASE, WORKSPACE_BASE, ARTIFACT_ROOT_BASE].contains(&base.as_str()) =>
+            {
+                return Err(format!(
+                    "files entry `{}` with base {} can only be resolved within the workspace",
+                    entry.pattern(),
+                    base
+                ).into())
+            }
+            FileEntry::Based { ref base, .. } => (root.join(base), entry.expand_with(root, &options)?),
+            _ => (root.to_path_buf(), entry.expand_with(root, &options)?),
+        };
+        resolved.extend(filter_expanded(config, files, index, &options, || Ok(entry_root), expanded)?);
+    }
+    sort_resolved(&mut resolved);
+    Ok(resolved
+        .into_iter()
+        .filter_map(|entry| match entry.source {
+            EntrySource::Path(src) => Some((src, entry.dest)),
+            EntrySource::Content(_) => None,
+        })
+        .collect())
+}
+
+/// reads the content of `entry` with its permission, that of the source file if not given
+fn read_content(entry: &PackEntry) -> Result<(Vec<u8>, Option<u32>)> {
+    match entry.source {
+        EntrySource::Path(ref src) => {
+            let mut content = Vec::new();
+            File::open(src)
+                .and_then(|mut f| f.read_to_end(&mut content))
+                .chain_err(|| format!("failed to read {}", src.display()))?;
+            Ok((content, entry.mode.or_else(|| fs::metadata(src).ok().map(|meta| archive::file_mode(&meta)))))
+        }
+        EntrySource::Content(ref content) => Ok((content.clone(), entry.mode)),
+    }
+}
+
+/// returns the members named `name`, or matching it if it is a glob pattern like `app-*`
+fn matching_members<'a, 'cfg>(ws: &'a Workspace<'cfg>, name: &str) -> Result<Vec<&'a Package>> {
+    if is_pattern(name) {
+        let pattern = glob::Pattern::new(name)?;
+        Ok(ws.members().filter(|p| pattern.matches(p.name())).collect())
+    } else if let Some(i) = name.find(':') {
+        let (name, version) = (&name[..i], &name[i + 1..]);
+        Ok(ws.members()
+            .filter(|p| p.package_id().name() == name && p.package_id().version().to_string() == version)
+            .collect())
+    } else {
+        Ok(ws.members().filter(|p| p.package_id().name() == name).collect())
+    }
+}
+
+/// finds the workspace member named `name`
+fn find_member<'a, 'cfg>(ws: &'a Workspace<'cfg>, name: &str) -> Result<&'a Package> {
+    let packages = matching_members(ws, name)?;
+    match packages.len() {
+        0 if is_pattern(name) => Err(format!("no package matches {}", name).into()),
+        0 if name.contains(':') => Err(format!("no package matches the name and the version of {}", name).into()),
+        0 => Err(format!("unknown package {}", name).into()),
+        1 => Ok(packages[0]),
+        _ if is_pattern(name) => {
+            let names = packages.iter().map(|p| p.name()).collect::<Vec<_>>();
+            Err(format!(
+                "{} matches multiple packages: {}. use CargoPackBuilder::build_all",
+                name,
+                names.join(", ")
+            ).into())
+        }
+        _ if name.contains(':') => Err(format!("ambiguous name {}", name).into()),
+        _ => Err(format!(
+            "ambiguous name {}. select the package with the version like {}:<version>",
+            name, name
+        ).into()),
+    }
+}
+
+/// compiles the negated `files` entry `negated` under `root`, one pattern per alternation
+fn negated_patterns(root: &Path, negated: &str) -> Result<Vec<glob::Pattern>> {
+    let mut patterns = Vec::new();
+    for alternative in brace::expand(negated)? {
+        let pattern_path = root.join(alternative);
+        let pattern = pattern_path
+            .to_str()
+            .ok_or_else(|| Error::from(format!("non UTF-8 path {:?}", pattern_path)))?;
+        patterns.push(glob::Pattern::new(pattern)?);
+    }
+    Ok(patterns)
+}
+
+/// removes the files matched by the negated patterns after the `index`th entry of `files`
+/// from `expanded`, the files of the entry. The patterns are rooted at `root`, the directory the
+/// entry is matched in, and `*` does not match `/` as in `.gitignore`. Names starting with `.`
+/// are matched by wildcards as `options` tells.
+fn subtract_negated(
+    files: &[FileEntry],
+    index: usize,
+    root: &Path,
+    options: &glob::MatchOptions,
+    expanded: &mut Vec<PackEntry>,
+) -> Result<()> {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..options.clone()
+    };
+    for later in &files[index + 1..] {
+        let negated = match later.negated() {
+            Some(negated) => negated,
+            None => continue,
+        };
+        let patterns = negated_patterns(root, negated)
+            .chain_err(|| format!("failed to resolve files entry `{}`", later.pattern()))?;
+        expanded.retain(|e| match e.source {
+            EntrySource::Path(ref src) => !patterns.iter().any(|p| p.matches_path_with(src, &options)),
+            EntrySource::Content(_) => true,
+        });
+    }
+    Ok(())
+}
+
+/// runs git with `args` in `dir` and returns what it prints
+fn git(dir: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .chain_err(|| "failed to run git")?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ).into());
+    }
+    Ok(output.stdout)
+}
+
+/// returns the canonical root of the git repository `dir` is in
+fn git_toplevel(dir: &Path) -> Result<PathBuf> {
+    let toplevel = git(dir, &["rev-parse", "--show-toplevel"])?;
+    Ok(PathBuf::from(String::from_utf8_lossy(&toplevel).trim()).canonicalize()?)
+}
+
+/// returns the paths git printed in `output` separated by NUL, as with `-z`, under `toplevel`
+fn git_paths(toplevel: &Path, output: &[u8]) -> HashSet<PathBuf> {
+    output
+        .split(|&b| b == 0)
+        .filter(|f| !f.is_empty())
+        .map(|f| toplevel.join(String::from_utf8_lossy(f).as_ref()))
+        .collect()
+}
+
+/// returns the name of the binary target built as `file_name`
+pub(crate) fn binary_target_name(file_name: &str) -> &str {
+    let suffix = env::consts::EXE_SUFFIX;
+    if !suffix.is_empty() && file_name.ends_with(suffix) {
+        &file_name[..file_name.len() - suffix.len()]
+    } else {
+        file_name
+    }
+}
+
+/// returns the profile to use when none is given explicitly: `CARGO_PACK_PROFILE` if set,
+/// otherwise `release`.
+pub fn default_profile() -> String {
+    env::var("CARGO_PACK_PROFILE").unwrap_or_else(|_| "release".to_string())
+}
+
+/// maps a profile name to the directory name under the target directory cargo writes its outputs to.
+///
+/// `dev` maps to `debug`. Any other profile, including custom ones, is its own directory name.
+pub fn profile_dir_name(profile: &str) -> &str {
+    match profile {
+        "dev" => "debug",
+        profile => profile,
+    }
+}
+
+/// creates `dir` and all of its parents. Succeeds if it already exists.
+/// Every method which writes outputs should create directories through this.
+fn ensure_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).chain_err(|| format!("failed to create directory {}", dir.display()))
+}
+
+/// creates the parent directory of the output file `out`
+fn ensure_parent_dir(out: &Path) -> Result<()> {
+    match out.parent() {
+        Some(parent) if parent != Path::new("") => ensure_dir(parent),
+        _ => Ok(()),
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// create a new CargoPack value
+    ///
+    /// ```rust,no_run
+    /// # extern crate cargo;
+    /// # extern crate cargo_pack;
+    /// # use cargo::util::Config;
+    /// # use cargo_pack::CargoPack;
+    /// # fn main() {
+    /// let config = Config::default().unwrap();
+    /// let pack = CargoPack::new(&config, None);
+    /// # }
+    /// ```
+
+    pub fn new<'a, P: Into<Option<String>>>(config: &'cfg Config, package_name: P) -> Result<Self> {
+        Self::builder(config).with_package_name(package_name).build()
+    }
+
+    /// returns a builder to configure how the CargoPack value is created
+    ///
+    /// ```rust,no_run
+    /// # extern crate cargo;
+    /// # extern crate cargo_pack;
+    /// # use cargo::util::Config;
+    /// # use cargo_pack::CargoPack;
+    /// # use std::path::PathBuf;
+    /// # fn main() {
+    /// let config = Config::default().unwrap();
+    /// let pack = CargoPack::builder(&config)
+    ///     .with_cwd(PathBuf::from("path/to/project"))
+    ///     .build();
+    /// # }
+    /// ```
+    pub fn builder(config: &'cfg Config) -> CargoPackBuilder<'cfg> {
+        CargoPackBuilder {
+            config: config,
+            package_name: None,
+            cwd: None,
+            manifest_path: None,
+            target: None,
+            target_dir: None,
+            lock_mode: None,
+            metadata_path: metadata_path_of("pack"),
+            offline: false,
+            config_overrides: None,
+            content_cache_limit: 0,
+            preserve_xattrs: false,
+            universal_macos: false,
+            transform: None,
+            strict: false,
+            artifact_root: None,
+            output_mtime: OutputMtime::Keep,
+            include_hidden: false,
+            include_binaries: true,
+            jobs: 1,
+            root_prefix: None,
+        }
+    }
+
+    /// create a new CargoPack value for the workspace member having both `name` and `version`,
+    /// for workspaces where members share a name. It is an error if no member has both.
+    pub fn with_package_id(config: &'cfg Config, name: &str, version: &str) -> Result<Self> {
+        Self::builder(config)
+            .with_package_name(format!("{}:{}", name, version))
+            .build()
+    }
+
+    /// create a new CargoPack value reading the config from `package.metadata.<namespace>`.
+    /// See `CargoPackBuilder::with_namespace`.
+    pub fn with_namespace<P: Into<Option<String>>>(
+        config: &'cfg Config,
+        package_name: P,
+        namespace: &str,
+    ) -> Result<Self> {
+        Self::builder(config)
+            .with_package_name(package_name)
+            .with_namespace(namespace.to_string())
+            .build()
+    }
+
+    /// create a new CargoPack value reading the config from the table at `path` of the manifest.
+    /// See `CargoPackBuilder::with_metadata_path`.
+    pub fn with_metadata_path<P: Into<Option<String>>>(
+        config: &'cfg Config,
+        package_name: P,
+        path: &[&str],
+    ) -> Result<Self> {
+        Self::builder(config)
+            .with_package_name(package_name)
+            .with_metadata_path(path.iter().map(|key| key.to_string()).collect())
+            .build()
+    }
+
+    /// returns the current working space of the package of `package_name`
+    pub fn ws(&self) -> &Workspace<'cfg> {
+        &self.ws
+    }
+
+    /// returns the PackConfig value
+    pub fn config(&self) -> &PackConfig {
+        &self.pack_config
+    }
+
+    /// returns the `Package` value of `package_name`.
+    ///
+    /// The selection is looked up in the members of the workspace on each call, so it fails if
+    /// the selected package is no longer one of them. The error names the selection and lists the
+    /// current members.
+    pub fn package(&self) -> Result<&Package> {
+        if let Some(ref name) = self.package_name {
+            find_member(self.ws(), name).chain_err(|| {
+                let members = self.ws().members().map(|p| p.name().to_string()).collect::<Vec<_>>();
+                format!(
+                    "failed to find the selected package {} in the workspace. members are {}",
+                    name,
+                    members.join(", ")
+                )
+            })
+        } else {
+            Ok(self.ws().current()?)
+        }
+    }
+
+    /// returns the package id of the selected package as `<name> <version> (<source>)`,
+    /// which is how `cargo metadata` identifies packages.
+    pub fn package_id_string(&self) -> Result<String> {
+        let id = self.package()?.package_id();
+        Ok(format!("{} {} ({})", id.name(), id.version(), id.source_id().to_url()))
+    }
+
+    /// selects another package of the workspace, or the current package with `None`,
+    /// and reloads its manifest and `package.metadata.pack` without reloading the workspace.
+    /// On error, the selection is left unchanged.
+    pub fn select_package(&mut self, package_name: Option<String>) -> Result<()> {
+        let mut pack_config = Self::load_pack_config(
+            self.ws(),
+            package_name.as_ref().map(|s| s.as_ref()),
+            &self.metadata_path,
+            self.config_overrides.as_ref(),
+            self.strict,
+        )?;
+        debug!("config: {:?}", pack_config);
+        let (_, manifest) = Self::read_manifest(self.ws(), package_name.as_ref().map(|s| s.as_ref()))?;
+        self.apply_target_cfgs(&mut pack_config)?;
+        self.package_name = package_name;
+        self.pack_config = pack_config;
+        self.manifest = manifest;
+        Ok(())
+    }
+
+    /// returns the parsed manifest of the selected package, or the root manifest of a virtual
+    /// workspace, so that packers can read other sections like `package.metadata.deb`.
+    ///
+    /// The value is the manifest as it was when the CargoPack value was created or the package
+    /// was selected.
+    pub fn manifest_value(&self) -> &Value {
+        &self.manifest
+    }
+
+    /// looks up the value at `path` of keys and array indices in `manifest_value`,
+    /// e.g. `&["package", "metadata", "deb"]`.
+    pub fn manifest_lookup(&self, path: &[&str]) -> Option<&Value> {
+        lookup_ref(&self.manifest, path)
+    }
+
+    /// returns whether the selected package is the root package of the workspace
+    pub fn is_workspace_root(&self) -> Result<bool> {
+        Ok(self.package()?.manifest_path() == self.ws().root().join("Cargo.toml"))
+    }
+
+    /// reads the manifest of the package of `package_name`, or the current manifest which is the
+    /// root manifest of a virtual workspace, and returns its path and content
+    fn read_manifest<'a>(ws: &'a Workspace, package_name: Option<&str>) -> Result<(&'a Path, Value)> {
+        let manifest = if let Some(name) = package_name {
+            find_member(ws, name)?.manifest_path()
+        } else {
+            ws.current_manifest()
+        };
+        debug!("reading manifest: {:?}", manifest);
+
+        let contents = paths::read(manifest)
+            .chain_err(|| format!("failed to read manifest {}", manifest.display()))?;
+        let root = toml::parse(&contents, &manifest, ws.config())
+            .chain_err(|| format!("failed to parse manifest {}", manifest.display()))?;
+        debug!("root: {:?}", root);
+        Ok((manifest, root))
+    }
+
+    /// returns the section at `metadata_path` of the manifest of `package_name` with its name, if any.
+    ///
+    /// `package.metadata.<namespace>` is looked up first. In the root manifest of the workspace,
+    /// `workspace.metadata.<namespace>` is looked up next, which is where a virtual workspace can
+    /// have the section. Other paths starting with `package` are looked up in the same way.
+    fn find_section<'a>(
+        ws: &'a Workspace,
+        package_name: Option<&str>,
+        metadata_path: &[String],
+    ) -> Result<(&'a Path, Option<(String, Value)>)> {
+        if metadata_path.is_empty() {
+            return Err("the metadata path of the config must not be empty".into());
+        }
+        let (manifest, root) = Self::read_manifest(ws, package_name)?;
+        let mut path = metadata_path.iter().map(|key| key.as_str()).collect::<Vec<_>>();
+        if let Some(data) = lookup(root.clone(), &path) {
+            return Ok((manifest, Some((path.join("."), data))));
+        }
+        if path[0] == "package" && manifest == ws.root().join("Cargo.toml") {
+            path[0] = "workspace";
+            if let Some(data) = lookup(root, &path) {
+                return Ok((manifest, Some((path.join("."), data))));
+            }
+        }
+        Ok((manifest, None))
+    }
+
+    fn decode_from_manifest_static<T: DeserializeOwned>(
+        ws: &Workspace,
+        package_name: Option<&str>,
+        metadata_path: &[String],
+    ) -> Result<T> {
+        let (manifest, section) = Self::find_section(ws, package_name, metadata_path)?;
+        let (name, data) = match section {
+            Some(section) => section,
+            None => {
+                return Err(format!(
+                    "no {} found in {}",
+                    metadata_path.join("."),
+                    manifest.display()
+                ).into())
+            }
+        };
+        if !data.is_table() {
+            return Err(format!(
+                "expected {} to be a table, found {} in {}",
+                name,
+                data.type_str(),
+                manifest.display()
+            ).into());
+        }
+        data.try_into()
+            .chain_err(|| format!("failed to decode {} in {}", name, manifest.display()))
+    }
+
+    /// reads the PackConfig of the package, adds the files of the host OS and applies `overrides`.
+    /// With `overrides`, a missing section is read as the default config.
+    /// With `strict`, keys not of PackConfig are rejected.
+    fn load_pack_config(
+        ws: &Workspace,
+        package_name: Option<&str>,
+        metadata_path: &[String],
+        overrides: Option<&PackConfig>,
+        strict: bool,
+    ) -> Result<PackConfig> {
+        let (manifest, section) = Self::find_section(ws, package_name, metadata_path)?;
+        if let Some((ref name, ref data)) = section {
+            if strict {
+                PackConfig::check_keys(name, data).chain_err(|| format!("failed to read {}", manifest.display()))?;
+            }
+        }
+        let mut pack_config = match overrides {
+            Some(_) if section.is_none() => PackConfig::default(),
+            _ => Self::decode_from_manifest_static(ws, package_name, metadata_path)?,
+        };
+        pack_config.merge_host_os();
+        if let Some(overrides) = overrides {
+            pack_config.override_with(overrides.clone());
+        }
+        pack_config.check_target_cfgs()?;
+        Ok(pack_config)
+    }
+
+    /// decode a value from the manifest toml file.
+    ///
+    /// The value is read from the same section as the PackConfig, `package.metadata.pack` or the
+    /// section given by `CargoPackBuilder::with_namespace` or `with_metadata_path`, so packers can add
+    /// their own keys to it.
+    /// The section is looked up in `package.metadata` first, then in `workspace.metadata` if the
+    /// manifest is the root manifest of the workspace, e.g. of a virtual workspace.
+    pub fn decode_from_manifest<'a, T: DeserializeOwned>(&self) -> Result<T> {
+        let package_name = self.package_name.as_ref().map(|s| s.as_ref());
+        Self::decode_from_manifest_static(self.ws(), package_name, &self.metadata_path)
+    }
+
+    /// returns the target triple binaries are looked up for.
+    ///
+    /// The triple given by `CargoPackBuilder::with_target` takes precedence over `build.target`
+    /// of cargo's configuration. `None` means the host.
+    pub fn target(&self) -> Result<Option<String>> {
+        if let Some(ref target) = self.target {
+            return Ok(Some(target.clone()));
+        }
+        Ok(self.ws().config().get_string("build.target")?.map(|v| v.val))
+    }
+
+    /// returns the target directory, respecting `CargoPackBuilder::with_target_dir`
+    fn target_dir(&self) -> PathBuf {
+        match self.target_dir {
+            Some(ref dir) => dir.clone(),
+            None => self.ws().target_dir().into_path_unlocked(),
+        }
+    }
+
+    /// returns `pack/<name>` in the target directory, a directory to write the outputs of the
+    /// selected package to, and creates it if missing. Each member of a workspace gets its own
+    /// directory, so packing several members does not mix their outputs. Frontends can write
+    /// to any other directory instead.
+    pub fn default_output_dir(&self) -> Result<PathBuf> {
+        let dir = self.target_dir().join("pack").join(self.package()?.name());
+        ensure_dir(&dir)?;
+        Ok(dir)
+    }
+
+    /// returns the directory outputs of `profile` are written to, respecting the target triple
+    /// and the target directory.
+    fn profile_dir(&self, profile: &str) -> Result<PathBuf> {
+        let mut dir = self.target_dir();
+        if let Some(target) = self.target()? {
+            dir.push(target);
+        }
+        dir.push(profile_dir_name(profile));
+        Ok(dir)
+    }
+
+    /// combines the binary `name` built for each of `UNIVERSAL_MACOS_TARGETS` into a universal
+    /// binary with `lipo`, and returns its path in `universal-apple-darwin/<profile>/` of the
+    /// target directory.
+    fn universal_binary(&self, name: &str, profile: &str) -> Result<PathBuf> {
+        let dir = self.target_dir();
+        let mut inputs = Vec::new();
+        for triple in UNIVERSAL_MACOS_TARGETS {
+            let path = dir.join(triple).join(profile_dir_name(profile)).join(name);
+            if !path.is_file() {
+                return Err(format!(
+                    "binary {} not found. a universal binary needs {} built for {} with profile {}",
+                    path.display(),
+                    name,
+                    UNIVERSAL_MACOS_TARGETS.join(" and "),
+                    profile
+                ).into());
+            }
+            inputs.push(path);
+        }
+        let out = dir.join("universal-apple-darwin")
+            .join(profile_dir_name(profile))
+            .join(name);
+        ensure_parent_dir(&out)?;
+        let output = Command::new("lipo")
+            .arg("-create")
+            .arg("-output")
+            .arg(&out)
+            .args(&inputs)
+            .output()
+            .chain_err(|| "failed to run lipo, which is needed to make universal binaries on macOS")?;
+        if !output.status.success() {
+            return Err(format!(
+                "lipo failed to make {}: {}",
+                out.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ).into());
+        }
+        Ok(out)
+    }
+
+    /// returns paths of the binaries of the package built with `profile`, only those listed in
+    /// `package.metadata.pack.bins` if given. It is an error if any of them are not built yet
+    /// or a listed name is not a binary target.
+    ///
+    /// Each binary is named after the `name` of its target as cargo names the output, so a
+    /// `[[bin]]` with a custom `name` and `path` is found by the name, not by the source file.
+    ///
+    /// The profile is `profile` if given, otherwise `CARGO_PACK_PROFILE`, otherwise `release`.
+    pub fn binaries<'a, P: Into<Option<&'a str>>>(&self, profile: P) -> Result<Vec<PathBuf>> {
+        let profile = profile.into().map(ToString::to_string).unwrap_or_else(default_profile);
+        self.binaries_of(self.package()?, &profile, self.bins())
+    }
+
+    /// returns the names of all binary targets of the selected package, in the order of the
+    /// manifest. Unlike `binaries`, neither `bins` nor the target directory is looked at, so
+    /// nothing needs to be built.
+    pub fn binary_names(&self) -> Result<Vec<String>> {
+        Ok(self.package()?
+            .targets()
+            .iter()
+            .filter(|t| t.is_bin())
+            .map(|t| t.name().to_string())
+            .collect())
+    }
+
+    fn bins(&self) -> Option<&[String]> {
+        self.pack_config.bins.as_ref().map(|bins| bins.as_slice())
+    }
+
+    /// returns binaries of `package`, only those named in `bins` if given
+    fn binaries_of(&self, package: &Package, profile: &str, bins: Option<&[String]>) -> Result<Vec<PathBuf>> {
+        let targets = package.targets().iter().filter(|t| t.is_bin()).collect::<Vec<_>>();
+        if let Some(bins) = bins {
+            for name in bins {
+                if targets.iter().all(|t| t.name() != name) {
+                    return Err(format!("bins: `{}` is not a binary target of {}", name, package.name()).into());
+                }
+            }
+        }
+        let dir = self.profile_dir(profile)?;
+        let mut binaries = Vec::new();
+        for target in targets {
+            if let Some(bins) = bins {
+                if !bins.iter().any(|name| name == target.name()) {
+                    continue;
+                }
+            }
+            if self.universal_macos {
+                binaries.push(self.universal_binary(target.name(), profile)?);
+                continue;
+            }
+            let path = dir.join(format!("{}{}", target.name(), env::consts::EXE_SUFFIX));
+            if !path.is_file() {
+                return Err(format!(
+                    "binary {} not found. build it with profile {} first",
+                    path.display(),
+                    profile
+                ).into());
+            }
+            binaries.push(path);
+        }
+        Ok(binaries)
+    }
+
+    /// returns the binaries built with `profile` as entries placed according to the layout.
+    /// The profile is chosen in the same way as `binaries`.
+    pub fn binary_entries<'a, P: Into<Option<&'a str>>>(&self, profile: P) -> Result<Vec<PackEntry>> {
+        let profile = profile.into().map(ToString::to_string).unwrap_or_else(default_profile);
+        self.binary_entries_of(self.package()?, &profile, self.bins())
+    }
+
+    fn binary_entries_of(&self, package: &Package, profile: &str, bins: Option<&[String]>) -> Result<Vec<PackEntry>> {
+        let layout = self.layout();
+        Ok(self.binaries_of(package, profile, bins)?
+            .into_iter()
+            .map(|path| {
+                let dest = layout.route_binary(&self.binary_file_name(package, path.file_name().unwrap()));
+                PackEntry {
+                    source: EntrySource::Path(path),
+                    dest: dest,
+                    mode: None,
+                }
+            })
+            .collect())
+    }
+
+    /// returns the file name to pack the binary `file_name` of `package` as, renamed by
+    /// `rename-binaries` if listed there
+    fn binary_file_name(&self, package: &Package, file_name: &OsStr) -> OsString {
+        let file_name_str = file_name.to_string_lossy();
+        let name = binary_target_name(&file_name_str);
+        let renamed = self.pack_config
+            .rename_binaries
+            .as_ref()
+            .and_then(|renames| renames.get(&format!("{}/{}", package.name(), name)));
+        match renamed {
+            Some(renamed) => OsString::from(format!("{}{}", renamed, &file_name_str[name.len()..])),
+            None => file_name.to_os_string(),
+        }
+    }
+
+    /// returns `package.<key>` of the manifest of the selected package, which must be a string if present
+    fn package_string(&self, key: &str) -> Result<Option<String>> {
+        let manifest = self.package()?.manifest_path();
+        match self.manifest_lookup(&["package", key]) {
+            None => Ok(None),
+            Some(&Value::String(ref s)) => Ok(Some(s.clone())),
+            Some(value) => Err(format!(
+                "expected package.{} to be a string, found {} in {}",
+                key,
+                value.type_str(),
+                manifest.display()
+            ).into()),
+        }
+    }
+
+    /// returns the Rust edition of the selected package. Defaults to `2015` as cargo does.
+    pub fn edition(&self) -> Result<String> {
+        Ok(self.package_string("edition")?.unwrap_or_else(|| "2015".to_string()))
+    }
+
+    /// returns the minimum supported Rust version, `package.rust-version`, of the selected package.
+    /// It is optional and absent in manifests written before it was introduced.
+    pub fn rust_version(&self) -> Result<Option<String>> {
+        self.package_string("rust-version")
+    }
+
+    /// returns the layout defined in `package.metadata.pack.layout` in the Cargo.toml.
+    pub fn layout(&self) -> Layout {
+        self.pack_config.layout.unwrap_or(Layout::Flat)
+    }
+
+    /// returns binaries of the package built with each of `profiles`, tagged with the profile.
+    /// Duplicated profiles are looked up only once.
+    pub fn binaries_for_profiles(&self, profiles: &[&str]) -> Result<Vec<(String, PathBuf)>> {
+        let mut seen = Vec::new();
+        let mut binaries = Vec::new();
+        for &profile in profiles {
+            if seen.contains(&profile) {
+                continue;
+            }
+            seen.push(profile);
+            for path in self.binaries(profile)? {
+                binaries.push((profile.to_string(), path));
+            }
+        }
+        Ok(binaries)
+    }
+
+    /// returns the commit hash of `HEAD` of the git repository the package is in.
+    ///
+    /// Returns `None` if the package is not in a git repository or git is not available.
+    pub fn git_commit(&self) -> Result<Option<String>> {
+        match git(self.package()?.root(), &["rev-parse", "HEAD"]) {
+            Ok(stdout) => Ok(Some(String::from_utf8_lossy(&stdout).trim().to_string())),
+            Err(e) => {
+                debug!("{}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// returns the canonical paths of the files tracked by the git repository `package` is in.
+    fn git_tracked_files(&self, package: &Package) -> Result<HashSet<PathBuf>> {
+        let not_in_git = || {
+            format!("git-tracked-only is set but {} is not in a git repository", package.root().display())
+        };
+        let toplevel = git_toplevel(package.root()).chain_err(&not_in_git)?;
+        Ok(git_paths(&toplevel, &git(&toplevel, &["ls-files", "-z"]).chain_err(&not_in_git)?))
+    }
+
+    /// returns the resolved files changed since the git ref `git_ref`, e.g. a tag of the previous
+    /// release, in the order of `resolved_files`.
+    ///
+    /// Changes are those reported by `git diff --name-only <git_ref>`, between the ref and the
+    /// working tree, so files never added to git are not included. It is an error if the package
+    /// is not in a git repository or `git_ref` is not a commit.
+    pub fn changed_since(&self, git_ref: &str) -> Result<Vec<PathBuf>> {
+        let package = self.package()?;
+        let toplevel = git_toplevel(package.root())
+            .chain_err(|| format!("{} is not in a git repository", package.root().display()))?;
+        git(&toplevel, &["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", git_ref)])
+            .chain_err(|| format!("`{}` is not a commit", git_ref))?;
+        let changed = git_paths(&toplevel, &git(&toplevel, &["diff", "--name-only", "-z", git_ref, "--"])?);
+        Ok(self.resolved_files()?
+            .into_iter()
+            .filter(|src| src.canonicalize().map(|src| changed.contains(&src)).unwrap_or(false))
+            .collect())
+    }
+
+    /// returns the archive format defined in `package.metadata.pack.format` in the Cargo.toml.
+    pub fn format(&self) -> Option<ArchiveFormat> {
+        self.pack_config.format
+    }
+
+    /// returns files defined in `package.metadata.pack.files` in the Cargo.toml.
+    pub fn files(&self) -> &[FileEntry] {
+        self.pack_config
+            .files
+            .as_ref()
+            .map(AsRef::as_ref)
+            .unwrap_or(&[])
+    }
+
+    /// returns the source paths of `resolved_files_with_dest`.
+    pub fn resolved_files(&self) -> Result<Vec<PathBuf>> {
+        Ok(self.resolved_files_with_dest()?
+            .into_iter()
+            .map(|(src, _)| src)
+            .collect())
+    }
+
+    /// returns the source paths of `resolved_files_with_dest` in the natural order of the
+    /// destinations, where `file2` comes before `file10`, to show to humans.
+    /// Archives keep the byte order of `resolved_files_with_dest` to be reproducible.
+    pub fn resolved_files_sorted_natural(&self) -> Result<Vec<PathBuf>> {
+        let mut files = self.resolved_files_with_dest()?;
+        files.sort_by(|&(_, ref a), &(_, ref b)| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+        Ok(files.into_iter().map(|(src, _)| src).collect())
+    }
+
+    /// returns files matched by `package.metadata.pack.files` paired with their destinations.
+    ///
+    /// The entries are `files`, then `files` of `os.<host os>`, then `files` of the `target.<cfg>`
+    /// sections matching the target in the order of the keys. An entry with `cfg` is replaced by
+    /// the entry it carries if the expression matches the target, or removed.
+    /// Each entry is resolved as follows. It is an error for an entry to match no file.
+    ///
+    /// - a pattern is a glob relative to the package root and lands at the matched path relative
+    ///   to the package root
+    /// - `{ glob, base }` is a glob relative to `base` and lands at the matched path relative to `base`
+    /// - `{ from, to }` lands at `to` after substituting the placeholders
+    /// - `{ from, base = "@workspace" }` is a file or a directory landing at its paths relative to
+    ///   the workspace root
+    /// - `{ dir, dest-transform }` is every file in `dir`, landing at its path relative to the
+    ///   package root passed through the transforms
+    /// - `{ package, files }` resolves the patterns in the member as patterns in this package do
+    /// - `{ package, artifact = "bin" }` are the binaries of the member
+    /// - a pattern prefixed with `!` matches nothing itself
+    ///
+    /// The files of an entry are then filtered in this order: by `git-tracked-only`, by the `!`
+    /// patterns after the entry, rooted at the directory the entry is matched in like its base,
+    /// and, unless they are binaries, by `exclude-extensions` and `exclude-larger-than`.
+    /// The layout routes the destinations other than renamed files and binaries. Finally the
+    /// files of all the entries are sorted by the destination and deduplicated.
+    pub fn resolved_files_with_dest(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        Ok(self.resolve()?
+            .into_iter()
+            .filter_map(|entry| match entry.source {
+                EntrySource::Path(src) => Some((src, entry.dest)),
+                EntrySource::Content(_) => None,
+            })
+            .collect())
+    }
+
+    /// resolves `files` into entries with absolute source paths, sorted by the destination.
+    /// Destinations are relative to the package root or to the `base` of the entry.
+    fn resolve(&self) -> Result<Vec<PackEntry>> {
+        self.resolve_with(false)
+    }
+
+    /// resolves `files` as `resolve` does, passing the contents through the transform if
+    /// `transform` is set
+    fn resolve_with(&self, transform: bool) -> Result<Vec<PackEntry>> {
+        let package = self.package()?;
+        let mut matched = self.match_in_threads(package)?;
+        let mut resolved = Vec::new();
+        let mut tracked = None;
+        for index in 0..self.files().len() {
+            resolved.extend(self.expand_at(package, index, transform, matched.remove(&index), &mut tracked)?);
+        }
+        sort_resolved(&mut resolved);
+        debug!("resolved files: {:?}", resolved);
+        if let Some(limit) = self.pack_config.warn_file_size {
+            for entry in &resolved {
+                let src = match entry.source {
+                    EntrySource::Path(ref src) => src,
+                    EntrySource::Content(_) => continue,
+                };
+                let size = fs::metadata(src)?.len();
+                if size > limit {
+                    warn!("{} is {} bytes, larger than warn-file-size {}", src.display(), size, limit);
+                }
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// returns everything to pack other than binaries: the resolved files followed by generated
+    /// files like the materialized license, `BUILD-INFO` and the inline files, then the license
+    /// file with `include-license` and `Cargo.lock` with `include-lockfile`.
+    ///
+    /// With `CargoPackBuilder::with_transform`, the entries other than the license file and
+    /// `Cargo.lock` have the transformed contents. It is an error for them to have the
+    /// destination of another file, unless `files` packs the same file there.
+    pub fn entries(&self) -> Result<Vec<PackEntry>> {
+        let mut entries = self.resolve_with(true)?;
+        for entry in self.generated_entries()? {
+            if let Some(existing) = entries.iter().find(|e| e.dest == entry.dest) {
+                if existing.source == entry.source {
+                    continue;
+                }
+                return Err(format!("{} is generated but another file is packed there", entry.dest.display()).into());
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    /// returns the files generated or added by the config to pack, transformed except for the
+    /// license file and `Cargo.lock`
+    fn generated_entries(&self) -> Result<Vec<PackEntry>> {
+        let mut entries = Vec::new();
+        for entry in self.materialized_license()?
+            .into_iter()
+            .chain(self.build_info()?)
+            .chain(self.inline_entries()?)
+        {
+            entries.push(self.transform(entry)?);
+        }
+        entries.extend(self.included_license()?);
+        entries.extend(self.included_lockfile()?);
+        Ok(entries)
+    }
+
+    /// returns `package.license-file` to pack when `include-license` is enabled. It is an
+    /// error if the package has no license file or it does not exist.
+    ///
+    /// The license is packed as the source file, so plans and their diffs tell when it changes.
+    pub fn included_license(&self) -> Result<Option<PackEntry>> {
+        if !self.pack_config.include_license.unwrap_or(false) {
+            return Ok(None);
+        }
+        let package = self.package()?;
+        let license_file = package
+            .manifest()
+            .metadata()
+            .license_file
+            .as_ref()
+            .ok_or("include-license is enabled but package.license-file is not set")?;
+        let src = package.root().join(license_file);
+        if !src.is_file() {
+            return Err(format!("package.license-file: {} does not exist", license_file).into());
+        }
+        let dest = PathBuf::from(src.file_name().ok_or_else(|| format!("invalid license-file {}", license_file))?);
+        Ok(Some(PackEntry {
+            source: EntrySource::Path(src),
+            dest: dest,
+            mode: None,
+        }))
+    }
+
+    /// returns `Cargo.lock` next to the root manifest of the workspace to pack when
+    /// `include-lockfile` is enabled. It is an error if it does not exist.
+    pub fn included_lockfile(&self) -> Result<Option<PackEntry>> {
+        if !self.pack_config.include_lockfile.unwrap_or(false) {
+            return Ok(None);
+        }
+        let src = self.ws().root().join("Cargo.lock");
+        if !src.is_file() {
+            return Err(format!(
+                "include-lockfile is enabled but {} does not exist. run `cargo generate-lockfile` \
+                 or commit the lockfile of the workspace",
+                src.display()
+            ).into());
+        }
+        Ok(Some(PackEntry {
+            source: EntrySource::Path(src),
+            dest: PathBuf::from("Cargo.lock"),
+            mode: None,
+        }))
+    }
+
+    /// returns the files of `inline` with the placeholders replaced
+    fn inline_entries(&self) -> Result<Vec<PackEntry>> {
+        let inline = match self.pack_config.inline {
+            Some(ref inline) if !inline.is_empty() => inline,
+            _ => return Ok(Vec::new()),
+        };
+        let vars = self.template_vars(self.package()?)?;
+        let mut entries = Vec::new();
+        for file in inline {
+            let content = template::render(&file.content, &vars)
+                .chain_err(|| format!("failed to render the content of inline file {}", file.dest))?;
+            entries.push(PackEntry {
+                source: EntrySource::Content(content.into_bytes()),
+                dest: PathBuf::from(&file.dest),
+                mode: file.mode.map(|m| m.0),
+            });
+        }
+        Ok(entries)
+    }
+
+    /// passes the content of `entry` through the transform given by `CargoPackBuilder::with_transform`.
+    /// The permission of the source file is kept.
+    fn transform(&self, entry: PackEntry) -> Result<PackEntry> {
+        let transform = match self.transform {
+            Some(ref transform) => transform,
+            None => return Ok(entry),
+        };
+        let (content, mode) = read_content(&entry)?;
+        let content = transform(&entry.dest, content)
+            .chain_err(|| format!("failed to transform {}", entry.dest.display()))?;
+        Ok(PackEntry {
+            source: EntrySource::Content(content),
+            dest: entry.dest,
+            mode: mode,
+        })
+    }
+
+    /// gzips `entry` into `<dest>.gz` if it is a man page and `gzip-man-pages` is enabled
+    fn gzip_man_page(&self, entry: PackEntry) -> Result<PackEntry> {
+        if !self.pack_config.gzip_man_pages.unwrap_or(false) || !is_installed_man_page(&entry.dest) {
+            return Ok(entry);
+        }
+        let (content, mode) = read_content(&entry)?;
+        let mut dest = entry.dest.into_os_string();
+        dest.push(".gz");
+        Ok(PackEntry {
+            source: EntrySource::Content(archive::gzip(&content)?),
+            dest: PathBuf::from(dest),
+            mode: mode,
+        })
+    }
+
+    /// returns the `LICENSE` entries materialized from `package.license`
+    /// when `materialize-license` is enabled and the package has no `license-file`.
+    ///
+    /// Multiple licenses like `MIT OR Apache-2.0` are materialized as `LICENSE-<id>` each.
+    /// It is an error if the license is not known to this crate.
+    pub fn materialized_license(&self) -> Result<Vec<PackEntry>> {
+        if !self.pack_config.materialize_license.unwrap_or(false) {
+            return Ok(Vec::new());
+        }
+        let package = self.package()?;
+        let metadata = package.manifest().metadata();
+        if metadata.license_file.is_some() {
+            debug!("license-file exists. not materializing the license");
+            return Ok(Vec::new());
+        }
+        let license = metadata
+            .license
+            .as_ref()
+            .ok_or("materialize-license is enabled but package.license is not set")?;
+        let copyright = metadata
+            .authors
+            .iter()
+            .map(|author| author.split('<').next().unwrap_or(author).trim())
+            .collect::<Vec<_>>()
+            .join(", ");
+        Ok(license::materialize(license, &copyright)?
+            .into_iter()
+            .map(|(name, text)| PackEntry {
+                source: EntrySource::Content(text.into_bytes()),
+                dest: PathBuf::from(name),
+                mode: None,
+            })
+            .collect())
+    }
+
+    /// returns the directory the named `base` of a files entry stands for,
+    /// or `None` if it is a path
+    fn named_base(&self, package: &Package, base: &str) -> Option<Result<PathBuf>> {
+        match base {
+            OUT_DIR_BASE => Some(self.out_dir(package, &default_profile())),
+            PACKAGE_BASE => Some(Ok(package.root().to_path_buf())),
+            WORKSPACE_BASE => Some(Ok(self.ws().root().to_path_buf())),
+            ARTIFACT_ROOT_BASE => Some(match self.artifact_root {
+                Some(ref dir) => Ok(self.real_path(package.root(), dir)),
+                None => match self.pack_config.artifact_root {
+                    Some(ref dir) => Ok(self.real_path(package.root(), dir)),
+                    None => Err("base is @artifact-root but no artifact root is given".into()),
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    /// returns where `path` of the config is read from: under the root prefix if it is absolute
+    /// and `CargoPackBuilder::with_root_prefix` is given, otherwise joined to `root`
+    fn real_path(&self, root: &Path, path: &Path) -> PathBuf {
+        match self.root_prefix {
+            Some(ref prefix) if path.is_absolute() => prefix.join(
+                path.components()
+                    .filter(|c| match *c {
+                        Component::Prefix(_) | Component::RootDir => false,
+                        _ => true,
+                    })
+                    .collect::<PathBuf>(),
+            ),
+            _ => root.join(path),
+        }
+    }
+
+    /// returns `entry` reading its absolute `from` or `base` under the root prefix, or `None`
+    /// if it reads nothing absolute or there is no root prefix
+    fn prefixed_entry(&self, entry: &FileEntry) -> Option<FileEntry> {
+        if self.root_prefix.is_none() {
+            return None;
+        }
+        match *entry {
+            FileEntry::Rename {
+                ref from,
+                ref to,
+                mode,
+            } if Path::new(from).is_absolute() => Some(FileEntry::Rename {
+                from: self.real_path(Path::new(""), Path::new(from)).to_string_lossy().into_owned(),
+                to: to.clone(),
+                mode: mode,
+            }),
+            FileEntry::Based { ref glob, ref base } if Path::new(base).is_absolute() => Some(FileEntry::Based {
+                glob: glob.clone(),
+                base: self.real_path(Path::new(""), Path::new(base)).to_string_lossy().into_owned(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// matches the entries of `files` which need nothing but the package root in threads, if
+    /// `CargoPackBuilder::with_jobs` allows. Returns the matches by the indices of the entries.
+    fn match_in_threads(&self, package: &Package) -> Result<HashMap<usize, Result<Vec<PackEntry>>>> {
+        if self.jobs <= 1 {
+            return Ok(HashMap::new());
+        }
+        let entries = self.files()
+            .iter()
+            .enumerate()
+            .filter(|&(_, entry)| match *entry {
+                FileEntry::Pattern(_) => entry.negated().is_none(),
+                FileEntry::Based { ref base, .. } => {
+                    ![OUT_DIR_BASE, PACKAGE_BASE, WORKSPACE_BASE, ARTIFACT_ROOT_BASE].contains(&base.as_str())
+                }
+                FileEntry::Dir { .. } => true,
+                _ => false,
+            })
+            .map(|(index, entry)| (index, self.prefixed_entry(entry).unwrap_or_else(|| entry.clone())))
+            .collect::<Vec<_>>();
+        if entries.len() < 2 {
+            return Ok(HashMap::new());
+        }
+        expand_in_threads(package.root(), entries, &self.match_options(), self.jobs)
+    }
+
+    /// expands `entry` of `package`, looking up the workspace member it refers to if any,
+    /// and places the destinations according to the layout. `matched` is the entry already
+    /// matched by `match_in_threads`, if it was.
+    fn expand(
+        &self,
+        package: &Package,
+        entry: &FileEntry,
+        matched: Option<Result<Vec<PackEntry>>>,
+    ) -> Result<Vec<PackEntry>> {
+        let expanded = match matched {
+            Some(matched) => matched,
+            None => self.match_entry(package, entry, &self.match_options()),
+        };
+        let mut expanded =
+            expanded.chain_err(|| format!("failed to resolve files entry `{}`", entry.pattern()))?;
+        if let FileEntry::Rename { ref to, .. } = *entry {
+            if to.contains('{') {
+                let dest = template::render(to, &self.template_vars(package)?)
+                    .chain_err(|| format!("failed to resolve files entry `{}`", entry.pattern()))?;
+                for e in &mut expanded {
+                    e.dest = PathBuf::from(&dest);
+                }
+            }
+        }
+        // renamed files are placed exactly where the user said and binaries are already routed
+        let routed = match *entry {
+            FileEntry::Rename { .. } | FileEntry::MemberArtifact { .. } => false,
+            _ => true,
+        };
+        if routed {
+            let layout = self.layout();
+            for e in &mut expanded {
+                e.dest = layout.route(package.name(), &e.dest);
+            }
+        }
+        Ok(expanded)
+    }
+
+    /// matches `entry` of `package` with `options`, looking up the workspace member it refers to
+    /// if any
+    fn match_entry(
+        &self,
+        package: &Package,
+        entry: &FileEntry,
+        options: &glob::MatchOptions,
+    ) -> Result<Vec<PackEntry>> {
+        if let Some(entry) = self.prefixed_entry(entry) {
+            return entry.expand_with(package.root(), options);
+        }
+        match *entry {
+            FileEntry::Based { ref glob, ref base } => match self.named_base(package, base) {
+                Some(dir) => dir.and_then(|dir| {
+                    FileEntry::Based {
+                        glob: glob.clone(),
+                        base: dir.to_string_lossy().into_owned(),
+                    }.expand_with(package.root(), options)
+                }),
+                None => entry.expand_with(package.root(), options),
+            },
+            FileEntry::MemberFiles {
+                ref package,
+                ref files,
+            } => find_member(self.ws(), package).and_then(|member| {
+                let mut expanded = Vec::new();
+                for pattern in files {
+                    expanded.extend(FileEntry::Pattern(pattern.clone()).expand_with(member.root(), options)?);
+                }
+                Ok(expanded)
+            }),
+            FileEntry::MemberArtifact {
+                ref package,
+                ref artifact,
+            } => find_member(self.ws(), package).and_then(|member| match artifact.as_str() {
+                "bin" => self.binary_entries_of(member, &default_profile(), None),
+                _ => Err(format!("unknown artifact `{}`, expected bin", artifact).into()),
+            }),
+            FileEntry::WorkspacePath { ref from, ref base } => {
+                self.expand_in_workspace(package, from, base, options)
+            }
+            _ => entry.expand_with(package.root(), options),
+        }
+    }
+
+    /// expands `from` of a `WorkspacePath` entry of `package` into its files placed relative to
+    /// the workspace root. It is an error for `from` to be outside of the workspace.
+    fn expand_in_workspace(
+        &self,
+        package: &Package,
+        from: &str,
+        base: &str,
+        options: &glob::MatchOptions,
+    ) -> Result<Vec<PackEntry>> {
+        if base != WORKSPACE_BASE {
+            return Err(format!("base of from must be @workspace, not {}", base).into());
+        }
+        let root = self.ws()
+            .root()
+            .canonicalize()
+            .chain_err(|| format!("failed to resolve {}", self.ws().root().display()))?;
+        let src = package.root().join(from);
+        let src = src.canonicalize()
+            .chain_err(|| format!("{} does not exist", src.display()))?;
+        if !src.starts_with(&root) {
+            return Err(format!("{} is outside of the workspace {}", src.display(), root.display()).into());
+        }
+        let paths = if src.is_dir() {
+            let src_str = src.to_str().ok_or_else(|| format!("non UTF-8 path {:?}", src))?;
+            let mut paths = Vec::new();
+            for path in glob::glob_with(&format!("{}/**/*", glob::Pattern::escape(src_str)), options)? {
+                let path = path?;
+                if path.is_file() {
+                    paths.push(path);
+                }
+            }
+            if paths.is_empty() {
+                return Err(format!("no files in {}", src.display()).into());
+            }
+            paths
+        } else {
+            vec![src]
+        };
+        Ok(paths
+            .into_iter()
+            .map(|path| PackEntry {
+                dest: path.strip_prefix(&root).unwrap_or(&path).to_path_buf(),
+                source: EntrySource::Path(path),
+                mode: None,
+            })
+            .collect())
+    }
+
+    /// expands the `index`th entry of `files` without the files removed by the negated
+    /// patterns after it, `exclude-extensions` and `exclude-larger-than`, in this order.
+    /// Negated entries themselves expand to nothing.
+    /// Files other than binaries are transformed, and man pages gzipped with `gzip-man-pages`,
+    /// if `transform` is set. `matched` is passed to `expand`. `tracked` holds the files tracked by
+    /// git once listed for `git-tracked-only`, so that git is run only once for all the entries.
+    fn expand_at(
+        &self,
+        package: &Package,
+        index: usize,
+        transform: bool,
+        matched: Option<Result<Vec<PackEntry>>>,
+        tracked: &mut Option<HashSet<PathBuf>>,
+    ) -> Result<Vec<PackEntry>> {
+        let files = self.files();
+        let entry = &files[index];
+        if entry.negated().is_some() {
+            return Ok(Vec::new());
+        }
+        let mut expanded = self.expand(package, entry, matched)?;
+        if self.pack_config.git_tracked_only.unwrap_or(false) && entry.is_glob() {
+            if tracked.is_none() {
+                *tracked = Some(self.git_tracked_files(package)?);
+            }
+            if let Some(ref tracked) = *tracked {
+                expanded.retain(|e| match e.source {
+                    EntrySource::Path(ref src) => src.canonicalize().map(|src| tracked.contains(&src)).unwrap_or(false),
+                    EntrySource::Content(_) => true,
+                });
+            }
+        }
+        expanded = filter_expanded(
+            &self.pack_config,
+            files,
+            index,
+            &self.match_options(),
+            || self.entry_root(package, entry),
+            expanded,
+        )?;
+        let binaries = match *entry {
+            FileEntry::MemberArtifact { .. } => true,
+            _ => false,
+        };
+        if transform && !binaries {
+            expanded = expanded
+                .into_iter()
+                .map(|e| self.transform(e).and_then(|e| self.gzip_man_page(e)))
+                .collect::<Result<_>>()?;
+        }
+        Ok(expanded)
+    }
+
+    /// returns the options to match glob patterns of `files` with
+    fn match_options(&self) -> glob::MatchOptions {
+        match_options(self.include_hidden)
+    }
+
+    /// returns the directory `entry` of `package` is matched in: the package root, its base, the
+    /// workspace root or the root of another member. Negated patterns after it are rooted there.
+    fn entry_root(&self, package: &Package, entry: &FileEntry) -> Result<PathBuf> {
+        match *entry {
+            FileEntry::Based { ref base, .. } => match self.named_base(package, base) {
+                Some(dir) => dir,
+                None => Ok(self.real_path(package.root(), Path::new(base))),
+            },
+            FileEntry::WorkspacePath { .. } => Ok(self.ws().root().to_path_buf()),
+            FileEntry::MemberFiles {
+                package: ref member,
+                ..
+            } => Ok(find_member(self.ws(), member)?.root().to_path_buf()),
+            _ => Ok(package.root().to_path_buf()),
+        }
+    }
+
+    /// returns `OUT_DIR` of the build script of `package` built with `profile`.
+    ///
+    /// It is `build/<name>-<hash>/out` in the profile directory. If there are more than one because
+    /// of older builds, the most recently modified is taken.
+    fn out_dir(&self, package: &Package, profile: &str) -> Result<PathBuf> {
+        let build_dir = self.profile_dir(profile)?.join("build");
+        let prefix = format!("{}-", package.name());
+        let mut found: Option<(SystemTime, PathBuf)> = None;
+        if let Ok(dirs) = fs::read_dir(&build_dir) {
+            for dir in dirs {
+                let dir = dir?;
+                let name = dir.file_name().to_string_lossy().into_owned();
+                // the hash is 16 hex digits, so that `foo-sys-<hash>` is not taken for `foo`
+                if !name.starts_with(&prefix) {
+                    continue;
+                }
+                let hash = &name[prefix.len()..];
+                if hash.len() != 16 || !hash.chars().all(|c| c.is_digit(16)) {
+                    continue;
+                }
+                let out = dir.path().join("out");
+                let modified = match fs::metadata(&out).and_then(|meta| meta.modified()) {
+                    Ok(modified) => modified,
+                    Err(_) => continue,
+                };
+                if found.as_ref().map(|&(latest, _)| modified > latest).unwrap_or(true) {
+                    found = Some((modified, out));
+                }
+            }
+        }
+        found.map(|(_, out)| out).ok_or_else(|| {
+            format!(
+                "OUT_DIR of {} not found in {}. the package needs a build script built with profile {}",
+                package.name(),
+                build_dir.display(),
+                profile
+            ).into()
+        })
+    }
+
+    /// returns the values of the placeholders in `to` of renamed files.
+    /// `{target}` is the target triple binaries are looked up for, or the host triple.
+    fn template_vars(&self, package: &Package) -> Result<Vec<(&'static str, String)>> {
+        let target = match self.target()? {
+            Some(target) => target,
+            None => self.ws().config().rustc()?.host.clone(),
+        };
+        Ok(vec![
+            ("name", package.name().to_string()),
+            ("version", package.version().to_string()),
+            ("target", target),
+        ])
+    }
+
+    /// returns the same entries as `entries`, expanding each `files` entry only when the iterator
+    /// reaches it.
+    ///
+    /// Entries come in the order of `files`, each expanded in the glob order, and are not
+    /// deduplicated nor sorted as a whole. An entry which fails to expand yields an `Err`
+    /// in place of its files and the iteration goes on.
+    pub fn entries_iter<'a>(&'a self) -> Box<Iterator<Item = Result<PackEntry>> + 'a> {
+        let package = match self.package() {
+            Ok(package) => package,
+            Err(e) => return Box::new(Some(Err(e)).into_iter()),
+        };
+        let mut tracked = None;
+        let files = (0..self.files().len()).flat_map(move |index| {
+            let items: Vec<Result<PackEntry>> = match self.expand_at(package, index, true, None, &mut tracked) {
+                Ok(expanded) => expanded.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            items
+        });
+        let generated = Some(()).into_iter().flat_map(move |()| {
+            let items: Vec<Result<PackEntry>> = match self.generated_entries() {
+                Ok(entries) => entries.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            items
+        });
+        Box::new(files.chain(generated))
+    }
+
+    /// returns the source paths of the resolved files grouped by the directory of their destination.
+    /// Files at the top of the package are grouped under the empty path.
+    pub fn grouped_destinations(&self) -> Result<BTreeMap<PathBuf, Vec<PathBuf>>> {
+        let mut groups = BTreeMap::new();
+        for (src, dest) in self.resolved_files_with_dest()? {
+            let dir = dest.parent().map(Path::to_path_buf).unwrap_or_default();
+            groups.entry(dir).or_insert_with(Vec::new).push(src);
+        }
+        Ok(groups)
+    }
+
+    /// fails if `total`, the size of what is to be packed, exceeds `max-total-size`
+    fn check_total_size(&self, total: u64) -> Result<()> {
+        match self.pack_config.max_total_size {
+            Some(limit) if total > limit => Err(format!(
+                "the files to pack are {} bytes in total, larger than max-total-size {}",
+                total, limit
+            ).into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// returns the total size in bytes of the resolved files
+    pub fn total_size(&self) -> Result<u64> {
+        let mut total = 0;
+        for src in self.resolved_files()? {
+            total += fs::metadata(src)?.len();
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {FileEntry, PackConfig, Size};
+    use cargo::core::Shell;
+    use cargo::util::Config;
+    use std::env;
+    use test_util::{self, TempDir};
+
+    #[test]
+    fn files_larger_than_warn_file_size_are_still_resolved() {
+        let dir = TempDir::new();
+        let large = dir.write("large.txt", "0123456789");
+        let small = dir.write("small.txt", "0");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"*.txt\"]\nwarn-file-size = 5");
+        assert_eq!(pack.config().warn_file_size, Some(5));
+        assert_eq!(pack.resolved_files().unwrap(), vec![large, small]);
+    }
+
+    #[test]
+    fn based_globs_are_placed_relative_to_the_base() {
+        let dir = TempDir::new();
+        dir.write("target/release/libfoo.so", "so");
+        dir.write("target/release/deps/libbar.so", "so");
+        dir.write("README.md", "readme");
+        dir.write("out-dir/a.txt", "a");
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "files = [\n\
+             { glob = \"**/*.so\", base = \"target/release\" },\n\
+             { glob = \"*.md\", base = \".\" },\n\
+             { glob = \"*.txt\", base = \"out-dir\" },\n\
+             { glob = \"out-dir/*\", base = \"@package\" },\n\
+             ]",
+        );
+        assert_eq!(
+            test_util::dests(&pack.entries().unwrap()),
+            vec!["README.md", "a.txt", "deps/libbar.so", "libfoo.so", "out-dir/a.txt"]
+        );
+    }
+
+    #[test]
+    fn profiles_map_to_their_directories() {
+        assert_eq!(super::profile_dir_name("dev"), "debug");
+        assert_eq!(super::profile_dir_name("release"), "release");
+        assert_eq!(super::profile_dir_name("dist"), "dist");
+
+        let dir = TempDir::new();
+        let debug = dir.write(&format!("target/debug/foo{}", env::consts::EXE_SUFFIX), "binary");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "");
+        assert_eq!(pack.binaries("dev").unwrap(), vec![debug]);
+        assert!(pack.binaries("dist").is_err());
+    }
+
+    #[test]
+    fn build_target_of_cargo_config_is_the_default_target() {
+        let dir = TempDir::new();
+        dir.write(".cargo/config", "[build]\ntarget = \"x86_64-unknown-linux-musl\"\n");
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]");
+        let binary = |triple: &str| {
+            dir.write(&format!("target/{}/release/foo{}", triple, env::consts::EXE_SUFFIX), "binary")
+        };
+        let musl = binary("x86_64-unknown-linux-musl");
+        let gnu = binary("x86_64-unknown-linux-gnu");
+        let config = Config::new(Shell::new(), dir.path().to_path_buf(), dir.path().join("home"));
+
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(pack.target().unwrap(), Some("x86_64-unknown-linux-musl".to_string()));
+        assert_eq!(pack.binaries("release").unwrap(), vec![musl]);
+
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_target("x86_64-unknown-linux-gnu".to_string())
+            .build()
+            .unwrap();
+        assert_eq!(pack.binaries("release").unwrap(), vec![gnu]);
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.write("run.sh", "run");
+        dir.write("app.map", "map");
+        let config: PackConfig = ::toml_crate::from_str(
+            "files = [\"README.md\", \"app.map\", \"\", \"missing.txt\"]\n\
+             default-packers = [\"docker\", \"docker\"]\n\
+             exclude-extensions = [\"map\"]",
+        ).unwrap();
+        let e = config.validate(dir.path()).err().unwrap().to_string();
+        let problems = e.lines().skip(1).map(|l| l.trim()).collect::<Vec<_>>();
+        assert_eq!(problems.len(), 4, "{}", e);
+        assert_eq!(problems[0], "default-packers: `docker` is listed more than once");
+        assert_eq!(problems[1], "exclude-extensions: `map` drops `app.map` listed in files");
+        assert_eq!(problems[2], "files: empty pattern");
+        assert!(problems[3].starts_with("files: `missing.txt`: "), "{}", e);
+
+        let config: PackConfig = ::toml_crate::from_str(
+            "files = [{ from = \"README.md\", to = \"bin/run\" }, { from = \"run.sh\", to = \"bin/run\" }]",
+        ).unwrap();
+        let e = config.validate(dir.path()).err().unwrap().to_string();
+        assert!(
+            e.ends_with("files: `bin/run` is the destination of both `README.md` and `run.sh`"),
+            "{}",
+            e
+        );
+
+        dir.write("logs/a.log", "a");
+        let config: PackConfig = ::toml_crate::from_str("files = [\"logs/*.log\", \"!logs/*\"]").unwrap();
+        let e = config.validate(dir.path()).err().unwrap().to_string();
+        assert!(e.ends_with("files: `!logs/*` removes every file of `logs/*.log`"), "{}", e);
+    }
+
+    #[test]
+    fn renames_leaving_the_archive_are_rejected_at_load() {
+        let dir = TempDir::new();
+        dir.write("run.sh", "run");
+        let config = test_util::config();
+        let error = |pack: &str| {
+            let manifest = dir.package(".", "foo", &format!("[package.metadata.pack]\n{}", pack));
+            test_util::builder(&config, &dir, &manifest).build().err().unwrap().to_string()
+        };
+        assert_eq!(
+            error("files = [{ from = \"run.sh\", to = \"../bin/run\" }]"),
+            "invalid package.metadata.pack.files: the destination `../bin/run` of `run.sh` must be a relative path \
+             without `..`"
+        );
+        assert_eq!(
+            error("files = [{ from = \"run.sh\", to = \"/usr/bin/run\" }]"),
+            "invalid package.metadata.pack.files: the destination `/usr/bin/run` of `run.sh` must be a relative path \
+             without `..`"
+        );
+        assert_eq!(
+            error("[package.metadata.pack.os.windows]\nfiles = [{ from = \"run.sh\", to = \"/usr/bin/run\" }]"),
+            "invalid package.metadata.pack.files: the destination `/usr/bin/run` of `run.sh` must be a relative path \
+             without `..`"
+        );
+        assert_eq!(
+            error("[package.metadata.pack.target.'cfg(unix)']\nfiles = [{ from = \"run.sh\", to = \"/usr/bin/run\" }]"),
+            "invalid package.metadata.pack.files: the destination `/usr/bin/run` of `run.sh` must be a relative path \
+             without `..`"
+        );
+        assert_eq!(
+            error("inline = [{ dest = \"../VERSION\", content = \"{version}\" }]"),
+            "invalid package.metadata.pack.inline: the destination `../VERSION` must be a relative path without `..`"
+        );
+        let manifest = dir.package(
+            ".",
+            "foo",
+            "[package.metadata.pack]\nfiles = [{ from = \"run.sh\", to = \"bin/./run\" }]",
+        );
+        assert!(test_util::builder(&config, &dir, &manifest).build().is_ok());
+    }
+
+    #[test]
+    fn only_the_root_package_is_the_workspace_root() {
+        let dir = TempDir::new();
+        let manifest = dir.package(
+            ".",
+            "app",
+            "[workspace]\nmembers = [\"member\"]\n\n[package.metadata.pack]",
+        );
+        dir.package("member", "member", "[package.metadata.pack]");
+        let config = test_util::config();
+        let root = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert!(root.is_workspace_root().unwrap());
+        let member = test_util::builder(&config, &dir, &manifest)
+            .with_package_name("member".to_string())
+            .build()
+            .unwrap();
+        assert!(!member.is_workspace_root().unwrap());
+    }
+
+    #[test]
+    fn select_package_reloads_the_config_and_keeps_it_on_errors() {
+        let dir = TempDir::new();
+        let manifest = dir.package(
+            ".",
+            "app",
+            "[workspace]\nmembers = [\"member\"]\n\n[package.metadata.pack]\nfiles = [\"README.md\"]",
+        );
+        dir.package("member", "member", "[package.metadata.pack]\nfiles = [\"NOTES.md\"]");
+        let config = test_util::config();
+        let mut pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(pack.files(), &[FileEntry::Pattern("README.md".to_string())]);
+
+        pack.select_package(Some("member".to_string())).unwrap();
+        assert_eq!(pack.package().unwrap().name(), "member");
+        assert_eq!(pack.files(), &[FileEntry::Pattern("NOTES.md".to_string())]);
+
+        assert!(pack.select_package(Some("unknown".to_string())).is_err());
+        assert_eq!(pack.package().unwrap().name(), "member");
+
+        pack.select_package(None).unwrap();
+        assert_eq!(pack.package().unwrap().name(), "app");
+    }
+
+    #[test]
+    fn scalar_section_is_an_error() {
+        let dir = TempDir::new();
+        let manifest = dir.package(".", "foo", "[package.metadata]\npack = \"docker\"");
+        let config = test_util::config();
+        let e = test_util::builder(&config, &dir, &manifest).build().err().unwrap();
+        assert!(e.to_string().contains("expected package.metadata.pack to be a table, found string"));
+    }
+
+    #[test]
+    fn missing_section_is_an_error() {
+        let dir = TempDir::new();
+        let manifest = dir.package(".", "foo", "");
+        let config = test_util::config();
+        let e = test_util::builder(&config, &dir, &manifest).build().err().unwrap();
+        assert!(e.to_string().contains("no package.metadata.pack found in"), "{}", e);
+    }
+
+    #[test]
+    fn bins_selects_the_binaries_to_pack() {
+        let dir = TempDir::new();
+        let bins = "[[bin]]\nname = \"foo\"\npath = \"src/main.rs\"\n\n\
+                    [[bin]]\nname = \"tool\"\npath = \"src/main.rs\"\n\n[package.metadata.pack]\n";
+        let manifest = dir.package(".", "foo", &format!("{}bins = [\"tool\"]", bins));
+        dir.binary("foo");
+        let tool = dir.binary("tool");
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(pack.binaries("release").unwrap(), vec![tool]);
+
+        let manifest = dir.package(".", "foo", &format!("{}bins = [\"tols\"]", bins));
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        let e = pack.binaries("release").err().unwrap();
+        assert_eq!(e.to_string(), "bins: `tols` is not a binary target of foo");
+    }
+
+    #[test]
+    fn offline_mode_requires_a_frozen_config() {
+        let dir = TempDir::new();
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]");
+        let mut config = test_util::config();
+        {
+            let e = test_util::builder(&config, &dir, &manifest)
+                .with_offline(true)
+                .build()
+                .err()
+                .unwrap();
+            assert!(e.to_string().starts_with("offline mode requires"), "{}", e);
+        }
+        config.configure(0, None, &None, true, false, &[]).unwrap();
+        assert!(test_util::builder(&config, &dir, &manifest).with_offline(true).build().is_ok());
+    }
+
+    #[test]
+    fn negated_patterns_subtract_from_preceding_entries() {
+        let dir = TempDir::new();
+        for path in &["logs/a.tmp", "logs/a.log", "logs/old/b.tmp"] {
+            dir.write(path, "log");
+        }
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"logs/**/*\", \"!logs/*.tmp\"]");
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["logs/a.log", "logs/old/b.tmp"]);
+    }
+
+    #[test]
+    fn negated_patterns_are_rooted_at_the_base() {
+        let dir = TempDir::new();
+        for path in &["assets/x.png", "assets/x.psd", "assets/sub/y.psd"] {
+            dir.write(path, "asset");
+        }
+        let manifest = dir.package(".", "foo", "");
+        let overrides = PackConfig {
+            files: Some(vec![
+                FileEntry::Based {
+                    glob: "**/*".to_string(),
+                    base: "assets".to_string(),
+                },
+                FileEntry::Pattern("!*.psd".to_string()),
+            ]),
+            ..PackConfig::default()
+        };
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_config_overrides(overrides)
+            .build()
+            .unwrap();
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["sub/y.psd", "x.png"]);
+    }
+
+    #[test]
+    fn config_overrides_replace_the_fields_set() {
+        let dir = TempDir::new();
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]\nfiles = [\"A\"]\nbins = [\"foo\"]");
+        let overrides = PackConfig {
+            files: Some(vec![FileEntry::Pattern("B".to_string())]),
+            ..PackConfig::default()
+        };
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_config_overrides(overrides.clone())
+            .build()
+            .unwrap();
+        assert_eq!(pack.files(), &[FileEntry::Pattern("B".to_string())]);
+        assert_eq!(pack.pack_config.bins, Some(vec!["foo".to_string()]));
+
+        let manifest = dir.package(".", "foo", "");
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_config_overrides(overrides)
+            .build()
+            .unwrap();
+        assert_eq!(pack.files(), &[FileEntry::Pattern("B".to_string())]);
+    }
+
+    #[test]
+    fn git_tracked_only_drops_untracked_matches() {
+        let dir = TempDir::new();
+        dir.write("assets/tracked.txt", "tracked");
+        dir.write("assets/untracked.txt", "untracked");
+        dir.write("docs/tracked.md", "tracked");
+        dir.write("NOTES.md", "named");
+        let git = |args: &[&str]| assert!(super::git(dir.path(), args).is_ok(), "git {:?}", args);
+        git(&["init", "-q"]);
+        git(&["add", "assets/tracked.txt", "docs/tracked.md"]);
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "files = [\"assets/*\", \"docs/*\", \"NOTES.md\"]\ngit-tracked-only = true",
+        );
+        assert_eq!(
+            test_util::dests(&pack.entries().unwrap()),
+            vec!["NOTES.md", "assets/tracked.txt", "docs/tracked.md"]
+        );
+    }
+
+    #[test]
+    fn virtual_workspaces_have_the_section_under_workspace_metadata() {
+        let dir = TempDir::new();
+        let manifest = dir.write(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"member\"]\n\n[workspace.metadata.pack]\nfiles = [\"README.md\"]\n",
+        );
+        dir.package("member", "member", "[package.metadata.pack]\nfiles = [\"NOTES.md\"]");
+        let config = test_util::config();
+        let root = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(root.files(), &[FileEntry::Pattern("README.md".to_string())]);
+        let member = test_util::builder(&config, &dir, &manifest)
+            .with_package_name("member".to_string())
+            .build()
+            .unwrap();
+        assert_eq!(member.files(), &[FileEntry::Pattern("NOTES.md".to_string())]);
+    }
+
+    #[test]
+    fn validate_packers_suggests_close_packers() {
+        let config = PackConfig {
+            default_packers: Some(vec!["docker".to_string(), "dokcer".to_string()]),
+            ..PackConfig::default()
+        };
+        let e = config.validate_packers(&["docker", "deb"]).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "invalid package.metadata.pack.default-packers:\n  unknown packer `dokcer`. did you mean `docker`?\n  \
+             known packers are docker, deb"
+        );
+        let config = PackConfig {
+            default_packers: Some(vec!["docker".to_string()]),
+            ..PackConfig::default()
+        };
+        assert!(config.validate_packers(&["docker", "deb"]).is_ok());
+    }
+
+    #[test]
+    fn suggest_returns_the_closest_candidate() {
+        let candidates = ["files", "format", "bins"];
+        assert_eq!(super::suggest("fles", &candidates), Some("files"));
+        assert_eq!(super::suggest("formta", &candidates), Some("format"));
+        assert_eq!(super::suggest("compression", &candidates), None);
+    }
+
+    #[test]
+    fn sizes_are_bytes_or_have_a_unit() {
+        assert_eq!("512".parse::<Size>().unwrap(), Size(512));
+        assert_eq!("50MB".parse::<Size>().unwrap(), Size(50_000_000));
+        assert_eq!("2 kib".parse::<Size>().unwrap(), Size(2048));
+        let e = "50 parsecs".parse::<Size>().err().unwrap();
+        assert_eq!(e.to_string(), "invalid size `50 parsecs`, expected bytes or a size like \"50MB\"");
+    }
+
+    #[test]
+    fn exclude_extensions_drops_files_case_insensitively() {
+        let dir = TempDir::new();
+        for path in &["dist/app.js", "dist/app.js.map", "dist/vendor.MAP", "dist/lib.d"] {
+            dir.write(path, "js");
+        }
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/*\"]\nexclude-extensions = [\"map\", \".d\"]");
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["dist/app.js"]);
+    }
+
+    #[test]
+    fn exclude_larger_than_drops_large_files() {
+        let dir = TempDir::new();
+        dir.write("dist/small.js", "js");
+        dir.write("dist/large.js", &"js".repeat(1024));
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/*\"]\nexclude-larger-than = \"1KiB\"");
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["dist/small.js"]);
+    }
+
+    #[test]
+    fn strict_rejects_unknown_keys_with_a_suggestion() {
+        let dir = TempDir::new();
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]\nflies = [\"README.md\"]");
+        let config = test_util::config();
+        assert!(test_util::builder(&config, &dir, &manifest).build().is_ok());
+        let e = test_util::builder(&config, &dir, &manifest)
+            .with_strict(true)
+            .build()
+            .err()
+            .unwrap();
+        let message = e.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n");
+        assert!(message.contains("invalid package.metadata.pack:\n"), "{}", message);
+        assert!(message.contains("\n  unknown key `flies`. did you mean `files`?\n"), "{}", message);
+        assert!(message.contains("\nknown keys are files, default-packers, "), "{}", message);
+    }
+
+    #[test]
+    fn natural_order_compares_numbers_by_value() {
+        let dir = TempDir::new();
+        for path in &["logs/file10", "logs/file2", "logs/file1"] {
+            dir.write(path, "log");
+        }
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"logs/*\"]");
+        let names = |files: Vec<::std::path::PathBuf>| {
+            files
+                .iter()
+                .map(|f| f.file_name().unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(pack.resolved_files_sorted_natural().unwrap()), vec!["file1", "file2", "file10"]);
+        let bytes = pack.resolved_files_with_dest().unwrap().into_iter().map(|(src, _)| src).collect();
+        assert_eq!(names(bytes), vec!["file1", "file10", "file2"]);
+    }
+
+    #[test]
+    fn matches_outside_of_the_base_are_errors() {
+        let dir = TempDir::new();
+        dir.write("app/artifacts/app.wasm", "wasm");
+        dir.write("app/secret/key.pem", "key");
+        dir.write("outside.txt", "outside");
+        let config = test_util::config();
+        let cause = |files: &str| {
+            let manifest = dir.package(
+                "app",
+                "app",
+                &format!("[package.metadata.pack]\nartifact-root = \"artifacts\"\nfiles = [{}]", files),
+            );
+            let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+            let e = pack.entries().err().unwrap();
+            e.iter().last().unwrap().to_string()
+        };
+        let e = cause("{ glob = \"../secret/*\", base = \"@artifact-root\" }");
+        assert!(e.ends_with(&format!("is outside of {}", dir.path().join("app/artifacts").display())), "{}", e);
+        let e = cause("{ glob = \"../*.txt\", base = \"@package\" }");
+        assert!(e.ends_with(&format!("is outside of {}", dir.path().join("app").display())), "{}", e);
+        let e = cause("\"../*.txt\"");
+        assert!(e.ends_with(&format!("is outside of {}", dir.path().join("app").display())), "{}", e);
+    }
+
+    #[test]
+    fn metadata_path_reads_the_config_from_a_deeper_table() {
+        let dir = TempDir::new();
+        let manifest = dir.package(
+            ".",
+            "foo",
+            "[package.metadata.pack]\nfiles = [\"A\"]\n\n[package.metadata.mycompany.pack]\nfiles = [\"B\"]",
+        );
+        let path = ["package", "metadata", "mycompany", "pack"];
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_metadata_path(path.iter().map(|key| key.to_string()).collect())
+            .build()
+            .unwrap();
+        assert_eq!(pack.files(), &[FileEntry::Pattern("B".to_string())]);
+
+        let e = test_util::builder(&config, &dir, &manifest)
+            .with_metadata_path(Vec::new())
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), "the metadata path of the config must not be empty");
+    }
+
+    #[test]
+    fn resolve_files_globs_excludes_and_dedups() {
+        let dir = TempDir::new();
+        for path in &["static/b.js", "static/a.js", "static/a.js.map", "static/a.tmp", "README.md"] {
+            dir.write(path, "file");
+        }
+        let pattern = |p: &str| FileEntry::Pattern(p.to_string());
+        let config = PackConfig {
+            files: Some(vec![
+                pattern("static/*"),
+                pattern("!static/*.tmp"),
+                pattern("static/a.js"),
+                pattern("README.md"),
+            ]),
+            exclude_extensions: Some(vec!["map".to_string()]),
+            ..PackConfig::default()
+        };
+        let dests = ::resolve_files(dir.path(), &config)
+            .unwrap()
+            .into_iter()
+            .map(|(_, dest)| dest.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(dests, vec!["README.md", "static/a.js", "static/b.js"]);
+
+        let config = PackConfig {
+            files: Some(vec![FileEntry::Based {
+                glob: "*".to_string(),
+                base: "@workspace".to_string(),
+            }]),
+            ..PackConfig::default()
+        };
+        let e = ::resolve_files(dir.path(), &config).err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "files entry `*` with base @workspace can only be resolved within the workspace"
+        );
+    }
+
+    #[test]
+    fn resolving_without_the_workspace_agrees_with_the_package() {
+        let dir = TempDir::new();
+        dir.write("static/a.js", "a");
+        dir.write("static/a.js.map", "map");
+        dir.write("static/b.tmp", "tmp");
+        dir.write("static/sub/c.js", "c");
+        dir.write("assets/d.txt", "d");
+        dir.write("docs/Guide.md", "guide");
+        dir.write("run.sh", "run");
+        dir.binary("foo");
+        let pattern = |p: &str| FileEntry::Pattern(p.to_string());
+        let based = |glob: &str, base: &str| FileEntry::Based {
+            glob: glob.to_string(),
+            base: base.to_string(),
+        };
+        let overrides = PackConfig {
+            files: Some(vec![
+                pattern("static/**/*"),
+                pattern("!static/*.tmp"),
+                based("*.txt", "assets"),
+                based("run.sh", "@package"),
+                FileEntry::Dir {
+                    dir: "docs".to_string(),
+                    dest_transform: Vec::new(),
+                },
+                FileEntry::Rename {
+                    from: "run.sh".to_string(),
+                    to: "bin/run".to_string(),
+                    mode: None,
+                },
+            ]),
+            exclude_extensions: Some(vec!["map".to_string()]),
+            ..PackConfig::default()
+        };
+        let config = test_util::config();
+        let manifest = dir.package(".", "foo", "");
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_config_overrides(overrides)
+            .build()
+            .unwrap();
+        let resolved = ::resolve_files(dir.path(), pack.config()).unwrap();
+        assert_eq!(resolved, pack.resolved_files_with_dest().unwrap());
+        assert_eq!(
+            resolved.iter().map(|&(_, ref dest)| dest.to_string_lossy().into_owned()).collect::<Vec<_>>(),
+            vec!["bin/run", "d.txt", "docs/Guide.md", "run.sh", "static/a.js", "static/sub/c.js"]
+        );
+    }
+
+    #[test]
+    fn dest_transforms_of_dir_entries_apply_in_order() {
+        let dir = TempDir::new();
+        dir.write("Docs/Guide.md", "guide");
+        dir.write("Docs/API/Index.md", "index");
+        let config = test_util::config();
+        let dests = |transform: &str| {
+            let pack = test_util::pack(
+                &config,
+                &dir,
+                &format!("files = [{{ dir = \"Docs\", dest-transform = {} }}]", transform),
+            );
+            pack.entries().map(|entries| test_util::dests(&entries))
+        };
+        assert_eq!(dests("\"lowercase\"").unwrap(), vec!["docs/api/index.md", "docs/guide.md"]);
+        assert_eq!(dests("\"prefix:share/\"").unwrap(), vec!["share/Docs/API/Index.md", "share/Docs/Guide.md"]);
+        assert_eq!(dests("\"strip-prefix:Docs/\"").unwrap(), vec!["API/Index.md", "Guide.md"]);
+        assert_eq!(
+            dests("[\"strip-prefix:Docs/\", \"lowercase\", \"prefix:doc/\"]").unwrap(),
+            vec!["doc/api/index.md", "doc/guide.md"]
+        );
+        let e = dests("\"strip-prefix:Doc/API/\"").err().unwrap();
+        let cause = e.iter().last().unwrap().to_string();
+        assert!(cause.ends_with("does not start with `Doc/API/` to strip"), "{}", cause);
+    }
+
+    #[test]
+    fn unknown_dest_transforms_are_errors() {
+        let e = "uppercase".parse::<::DestTransform>().err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "unknown dest-transform `uppercase`, expected lowercase, prefix:<prefix> or strip-prefix:<prefix>"
+        );
+    }
+
+    #[test]
+    fn destinations_outside_of_the_archive_are_errors() {
+        let dir = TempDir::new();
+        let guide = dir.write("Docs/Guide.md", "guide");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [{ dir = \"Docs\", dest-transform = \"prefix:../\" }]");
+        let e = pack.plan().err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "the destination ../Docs/Guide.md of {} must be a relative path without `..`",
+                guide.display()
+            )
+        );
+    }
+
+    #[test]
+    fn include_hidden_toggles_wildcards_matching_dotfiles() {
+        let dir = TempDir::new();
+        dir.write("config/.env", "env");
+        dir.write("config/app.toml", "app");
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]\nfiles = [\"config/*\"]");
+        let config = test_util::config();
+        let dests = |include| {
+            let pack = test_util::builder(&config, &dir, &manifest)
+                .with_include_hidden(include)
+                .build()
+                .unwrap();
+            test_util::dests(&pack.entries().unwrap())
+        };
+        assert_eq!(dests(true), vec!["config/.env", "config/app.toml"]);
+        assert_eq!(dests(false), vec!["config/app.toml"]);
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["config/app.toml"]);
+
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]\nfiles = [\"config/.env\"]");
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_include_hidden(false)
+            .build()
+            .unwrap();
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["config/.env"]);
+    }
+
+    #[test]
+    fn negated_wildcards_skip_hidden_files_without_include_hidden() {
+        let dir = TempDir::new();
+        dir.write("conf/.env", "env");
+        dir.write("conf/a.txt", "a");
+        let manifest = dir.package(
+            ".",
+            "foo",
+            "[package.metadata.pack]\nfiles = [\"conf/.env\", \"conf/a.txt\", \"!conf/*\"]",
+        );
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_include_hidden(false)
+            .build()
+            .unwrap();
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["conf/.env"]);
+    }
+
+    #[test]
+    fn workspace_paths_can_be_siblings_within_the_workspace() {
+        let dir = TempDir::new();
+        dir.write("Cargo.toml", "[workspace]\nmembers = [\"app\"]\n");
+        dir.write("plugins/foo/assets/icon.svg", "svg");
+        dir.write("plugins/foo/assets/fonts/a.ttf", "ttf");
+        let manifest = dir.package(
+            "app",
+            "app",
+            "[package.metadata.pack]\nfiles = [{ from = \"../plugins/foo/assets\", base = \"@workspace\" }]",
+        );
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(
+            test_util::dests(&pack.entries().unwrap()),
+            vec!["plugins/foo/assets/fonts/a.ttf", "plugins/foo/assets/icon.svg"]
+        );
+
+        let outside = TempDir::new();
+        outside.write("assets/icon.svg", "svg");
+        let from = outside.path().join("assets").to_string_lossy().replace('\\', "/");
+        let manifest = dir.package(
+            "app",
+            "app",
+            &format!("[package.metadata.pack]\nfiles = [{{ from = \"{}\", base = \"@workspace\" }}]", from),
+        );
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        let e = pack.entries().err().unwrap();
+        let cause = e.iter().last().unwrap().to_string();
+        assert!(cause.contains(" is outside of the workspace "), "{}", cause);
+    }
+
+    #[test]
+    fn stale_selections_name_the_current_members() {
+        use cargo::core::Workspace;
+
+        let dir = TempDir::new();
+        let manifest = dir.package(
+            ".",
+            "app",
+            "[workspace]\nmembers = [\"member\"]\n\n[package.metadata.pack]",
+        );
+        dir.package("member", "member", "[package.metadata.pack]");
+        let config = test_util::config();
+        let mut pack = test_util::builder(&config, &dir, &manifest)
+            .with_package_name("member".to_string())
+            .build()
+            .unwrap();
+        assert_eq!(pack.package().unwrap().name(), "member");
+
+        dir.package(".", "app", "[workspace]\nmembers = []\n\n[package.metadata.pack]");
+        pack.ws = Workspace::new(&manifest, &config).unwrap();
+        let e = pack.package().err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "failed to find the selected package member in the workspace. members are app"
+        );
+    }
+
+    #[test]
+    fn gzip_man_pages_gzips_installed_man_pages() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+        use EntrySource;
+
+        let dir = TempDir::new();
+        dir.write("doc/foo.1", ".TH FOO 1");
+        dir.write("README.md", "readme");
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "files = [\"doc/foo.1\", \"README.md\"]\nlayout = \"fhs\"\ngzip-man-pages = true",
+        );
+        let entries = pack.entries().unwrap();
+        assert_eq!(test_util::dests(&entries), vec!["share/foo/README.md", "share/man/man1/foo.1.gz"]);
+        let content = match entries[1].source {
+            EntrySource::Content(ref content) => content.clone(),
+            ref source => panic!("not gzipped: {:?}", source),
+        };
+        let mut page = String::new();
+        GzDecoder::new(content.as_slice()).read_to_string(&mut page).unwrap();
+        assert_eq!(page, ".TH FOO 1");
+        match entries[0].source {
+            EntrySource::Path(ref src) => assert_eq!(src, &dir.path().join("README.md")),
+            ref source => panic!("transformed: {:?}", source),
+        }
+    }
+
+    #[test]
+    fn inline_files_have_the_placeholders_replaced() {
+        use {EntrySource, PackEntry};
+
+        let dir = TempDir::new();
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "[[package.metadata.pack.inline]]\ndest = \"VERSION\"\ncontent = \"{name} {version}\\n\"\nmode = \"600\"",
+        );
+        assert_eq!(
+            pack.entries().unwrap(),
+            vec![
+                PackEntry {
+                    source: EntrySource::Content(b"foo 0.1.0\n".to_vec()),
+                    dest: "VERSION".into(),
+                    mode: Some(0o600),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn inline_files_at_the_destination_of_another_file_are_errors() {
+        let dir = TempDir::new();
+        dir.write("VERSION", "0.0.0");
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "files = [\"VERSION\"]\n\n[[package.metadata.pack.inline]]\ndest = \"VERSION\"\ncontent = \"{version}\"",
+        );
+        let e = pack.entries().err().unwrap();
+        assert_eq!(e.to_string(), "VERSION is generated but another file is packed there");
+    }
+
+    #[test]
+    fn matching_in_threads_resolves_the_same_files() {
+        let dir = TempDir::new();
+        for i in 0..20 {
+            dir.write(&format!("assets/{}/a.txt", i), "a");
+            dir.write(&format!("assets/{}/b.log", i), "b");
+        }
+        dir.write("README.md", "readme");
+        let files = (0..20)
+            .map(|i| format!("\"assets/{}/*\"", i))
+            .chain(vec!["\"README.md\"".to_string(), "\"!assets/1*/*.log\"".to_string()])
+            .collect::<Vec<_>>();
+        let manifest = dir.package(
+            ".",
+            "foo",
+            &format!("[package.metadata.pack]\nfiles = [{}]\nexclude-extensions = [\"md\"]", files.join(", ")),
+        );
+        let config = test_util::config();
+        let entries = |jobs| {
+            test_util::builder(&config, &dir, &manifest)
+                .with_jobs(jobs)
+                .build()
+                .unwrap()
+                .entries()
+                .unwrap()
+        };
+        let serial = entries(1);
+        assert_eq!(serial.len(), 29);
+        assert_eq!(entries(4), serial);
+        assert_eq!(entries(64), serial);
+    }
+
+    #[test]
+    fn binary_names_lists_the_targets_without_building() {
+        let dir = TempDir::new();
+        let manifest = dir.package(
+            ".",
+            "foo",
+            "[[bin]]\nname = \"server\"\npath = \"src/main.rs\"\n\n\
+             [[bin]]\nname = \"cli-tool\"\npath = \"src/main.rs\"\n\n\
+             [package.metadata.pack]\nbins = [\"server\"]",
+        );
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(pack.binary_names().unwrap(), vec!["server", "cli-tool"]);
+        assert!(!dir.path().join("target").exists());
+    }
+
+    /// a config with every field set, `n` making the values of each call differ
+    fn full_config(n: u32, files: &str) -> PackConfig {
+        ::toml_crate::from_str(&format!(
+            r#"
+            files = {files}
+            default-packers = ["packer{n}"]
+            warn-file-size = {n}
+            max-total-size = {n}
+            format = "{format}"
+            compression-level = {n}
+            store-extensions = ["ext{n}"]
+            materialize-license = {even}
+            include-license = {even}
+            include-lockfile = {even}
+            include-build-info = {even}
+            layout = "{layout}"
+            case-collisions = "{case}"
+            bins = ["bin{n}"]
+            rename-binaries = {{ bin{n} = "renamed{n}" }}
+            check-binary-collisions = {even}
+            git-tracked-only = {even}
+            artifact-root = "root{n}"
+            exclude-extensions = ["ext{n}"]
+            exclude-larger-than = {n}
+            gzip-man-pages = {even}
+            inline = [{{ dest = "FILE{n}", content = "{n}", mode = "644" }}]
+            download-url-template = "https://example.com/{n}/{{archive}}"
+            artifact-dir = "dir{n}"
+            include-checksums = {even}
+            os = {{ linux = {{ files = ["linux{n}"] }} }}
+            target = {{ 'cfg(unix)' = {{ files = ["unix{n}"] }} }}
+            "#,
+            files = files,
+            n = n,
+            even = n % 2 == 0,
+            format = if n % 2 == 0 { "zip" } else { "tar.gz" },
+            layout = if n % 2 == 0 { "fhs" } else { "flat" },
+            case = if n % 2 == 0 { "warn" } else { "error" },
+        )).unwrap()
+    }
+
+    #[test]
+    fn merge_concatenates_files_and_takes_the_other_fields_of_the_overlay() {
+        let merged = PackConfig::merge(full_config(1, r#"["a", "b"]"#), full_config(2, r#"["b", "c"]"#));
+        let pattern = |p: &str| FileEntry::Pattern(p.to_string());
+        assert_eq!(merged.files, Some(vec![pattern("a"), pattern("b"), pattern("c")]));
+        let expected = PackConfig {
+            files: merged.files.clone(),
+            ..full_config(2, "[]")
+        };
+        assert_eq!(format!("{:?}", merged), format!("{:?}", expected));
+    }
+
+    #[test]
+    fn merge_keeps_the_fields_unset_in_the_overlay() {
+        let base = full_config(1, r#"["a"]"#);
+        assert!(!format!("{:?}", base).contains("None"), "{:?}", base);
+        let merged = PackConfig::merge(base.clone(), PackConfig::default());
+        assert_eq!(format!("{:?}", merged), format!("{:?}", base));
+        let merged = PackConfig::merge(PackConfig::default(), base.clone());
+        assert_eq!(format!("{:?}", merged), format!("{:?}", base));
+    }
+
+    #[test]
+    fn include_lockfile_packs_the_lockfile_of_the_workspace() {
+        use {EntrySource, PackEntry};
+
+        let dir = TempDir::new();
+        let lockfile = dir.write("Cargo.lock", "# lock");
+        dir.write("Cargo.toml", "[workspace]\nmembers = [\"app\"]\n");
+        let manifest = dir.package("app", "app", "[package.metadata.pack]\ninclude-lockfile = true");
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        assert_eq!(
+            pack.entries().unwrap(),
+            vec![
+                PackEntry {
+                    source: EntrySource::Path(lockfile),
+                    dest: "Cargo.lock".into(),
+                    mode: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn include_lockfile_without_the_lockfile_is_an_error() {
+        let dir = TempDir::new();
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "include-lockfile = true");
+        let e = pack.entries().err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            format!(
+                "include-lockfile is enabled but {} does not exist. run `cargo generate-lockfile` \
+                 or commit the lockfile of the workspace",
+                dir.path().join("Cargo.lock").display()
+            )
+        );
+    }
+
+    #[test]
+    fn root_prefix_applies_to_reads_of_absolute_paths_only() {
+        use std::path::PathBuf;
+
+        let dir = TempDir::new();
+        let sandbox = TempDir::new();
+        let logo = sandbox.write("opt/assets/logo.png", "png");
+        let conf = sandbox.write("etc/app.conf", "conf");
+        let manifest = dir.package(
+            ".",
+            "foo",
+            "[package.metadata.pack]\nfiles = [\n\
+             { glob = \"*.png\", base = \"/opt/assets\" },\n\
+             { from = \"/etc/app.conf\", to = \"conf/app.conf\" },\n]",
+        );
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest)
+            .with_root_prefix(sandbox.path().to_path_buf())
+            .build()
+            .unwrap();
+        assert_eq!(
+            pack.resolved_files_with_dest().unwrap(),
+            vec![(conf, PathBuf::from("conf/app.conf")), (logo, PathBuf::from("logo.png"))]
+        );
+    }
+
+    #[test]
+    fn double_star_matches_zero_or_more_directories() {
+        let dir = TempDir::new();
+        dir.write("assets/logo.png", "0");
+        dir.write("assets/icons/app.png", "1");
+        dir.write("assets/icons/app/small/logo.png", "3");
+        dir.write("assets/icons/app/readme.txt", "txt");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"assets/**/*.png\"]");
+        assert_eq!(
+            test_util::dests(&pack.entries().unwrap()),
+            vec!["assets/icons/app/small/logo.png", "assets/icons/app.png", "assets/logo.png"]
+        );
+    }
+
+    #[test]
+    fn negated_double_star_matches_zero_or_more_directories() {
+        let dir = TempDir::new();
+        dir.write("assets/logo.png", "0");
+        dir.write("assets/logo.txt", "0");
+        dir.write("assets/icons/app.png", "1");
+        dir.write("assets/icons/app/small/logo.png", "3");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"assets/**/*\", \"!assets/**/*.png\"]");
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["assets/logo.txt"]);
+    }
+
+    /// writes a package whose files entries for `cfgs` are `<i>.txt` for the `i`th of `cfgs` and
+    /// returns what it packs
+    fn files_for_cfgs(cfgs: &[&str]) -> ::Result<Vec<String>> {
+        let dir = TempDir::new();
+        let files = cfgs.iter()
+            .enumerate()
+            .map(|(i, cfg)| {
+                dir.write(&format!("{}.txt", i), "cfg");
+                format!("{{ glob = \"{}.txt\", base = \".\", cfg = {:?} }}", i, cfg)
+            })
+            .collect::<Vec<_>>();
+        let manifest = dir.package(".", "foo", &format!("[package.metadata.pack]\nfiles = [{}]", files.join(", ")));
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest).build()?;
+        Ok(test_util::dests(&pack.entries()?))
+    }
+
+    #[test]
+    fn entries_with_cfg_are_packed_for_matching_targets() {
+        let os = format!("target_os = \"{}\"", env::consts::OS);
+        let arch = format!("target_arch = \"{}\"", env::consts::ARCH);
+        let packed = files_for_cfgs(&[
+            &format!("cfg({})", os),
+            &format!("cfg(not({}))", os),
+            &format!("cfg(all({}, {}))", os, arch),
+            &format!("cfg(all({}, not({})))", os, arch),
+            "cfg(any(unix, windows))",
+            "cfg(target_os = \"no-such-os\")",
+        ]).unwrap();
+        assert_eq!(packed, vec!["0.txt", "2.txt", "4.txt"]);
+    }
+
+    #[test]
+    fn invalid_cfg_of_entries_is_an_error_at_load() {
+        for cfg in &["cfg(all(unix)", "target_os = \"linux\"", "cfg(unix = )"] {
+            let e = files_for_cfgs(&[cfg]).err().unwrap();
+            assert_eq!(e.to_string(), "invalid cfg of package.metadata.pack.files", "{}", cfg);
+        }
+    }
+
+    #[test]
+    fn target_sections_add_files_for_matching_targets() {
+        let dir = TempDir::new();
+        dir.write("host.txt", "host");
+        dir.write("other.txt", "other");
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            &format!(
+                "[package.metadata.pack.target.'cfg(target_os = \"{}\")']\nfiles = [\"host.txt\"]\n\
+                 [package.metadata.pack.target.'cfg(target_os = \"no-such-os\")']\nfiles = [\"other.txt\"]",
+                env::consts::OS
+            ),
+        );
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["host.txt"]);
     }
 }
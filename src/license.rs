@@ -0,0 +1,53 @@
+//! license texts bundled to materialize `LICENSE` files from SPDX identifiers
+
+use error::*;
+
+/// SPDX identifiers and their texts. `{copyright}` is replaced with the copyright holders.
+const LICENSES: &[(&str, &str)] = &[
+    ("Apache-2.0", include_str!("licenses/Apache-2.0")),
+    ("BSD-2-Clause", include_str!("licenses/BSD-2-Clause")),
+    ("BSD-3-Clause", include_str!("licenses/BSD-3-Clause")),
+    ("ISC", include_str!("licenses/ISC")),
+    ("MIT", include_str!("licenses/MIT")),
+    ("MPL-2.0", include_str!("licenses/MPL-2.0")),
+];
+
+/// splits a license expression into identifiers.
+/// Both the SPDX form `MIT OR Apache-2.0` and the legacy form `MIT/Apache-2.0` are accepted.
+fn identifiers(expr: &str) -> Vec<&str> {
+    expr.split(|c: char| c.is_whitespace() || c == '/' || c == '(' || c == ')')
+        .filter(|id| !id.is_empty() && *id != "OR" && *id != "AND")
+        .collect()
+}
+
+/// returns the license file names and texts of the license expression `expr`.
+///
+/// A single license is named `LICENSE` and multiple licenses are named `LICENSE-<id>`.
+/// It is an error if any identifier is not bundled.
+pub fn materialize(expr: &str, copyright: &str) -> Result<Vec<(String, String)>> {
+    let ids = identifiers(expr);
+    if ids.is_empty() {
+        return Err(format!("no license identifier in `{}`", expr).into());
+    }
+    let mut files = Vec::new();
+    for id in &ids {
+        let text = match LICENSES.iter().find(|&&(known, _)| known == *id) {
+            Some(&(_, text)) => text.replace("{copyright}", copyright),
+            None => {
+                let known = LICENSES.iter().map(|&(known, _)| known).collect::<Vec<_>>();
+                return Err(format!(
+                    "cannot materialize unknown license `{}`. known licenses are {}",
+                    id,
+                    known.join(", ")
+                ).into());
+            }
+        };
+        let name = if ids.len() == 1 {
+            "LICENSE".to_string()
+        } else {
+            format!("LICENSE-{}", id)
+        };
+        files.push((name, text));
+    }
+    Ok(files)
+}
@@ -0,0 +1,65 @@
+//! advisory lock which serializes concurrent packing of the same workspace
+
+use {CargoPack, ensure_dir};
+use error::*;
+use fs2::{self, FileExt};
+use std::fs::{File, OpenOptions};
+
+/// what to do when another process holds the lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// blocks until the lock is released
+    Wait,
+    /// fails immediately
+    FailFast,
+}
+
+/// a held lock. Released when dropped.
+pub struct PackLock {
+    file: File,
+}
+
+impl Drop for PackLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// acquires the lock if `CargoPackBuilder::with_lock` is given.
+    ///
+    /// The lock file is `.cargo-pack.lock` in the target directory. It is left in place after the
+    /// lock is released; only the advisory lock on it matters.
+    pub(crate) fn lock(&self) -> Result<Option<PackLock>> {
+        let mode = match self.lock_mode {
+            Some(mode) => mode,
+            None => return Ok(None),
+        };
+        let dir = self.target_dir();
+        ensure_dir(&dir)?;
+        let path = dir.join(".cargo-pack.lock");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .chain_err(|| format!("failed to open lock file {}", path.display()))?;
+        match FileExt::try_lock_exclusive(&file) {
+            Ok(()) => (),
+            Err(ref e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+                match mode {
+                    LockMode::FailFast => {
+                        return Err(format!("{} is locked by another process", path.display()).into())
+                    }
+                    LockMode::Wait => {
+                        info!("waiting for the lock {}", path.display());
+                        FileExt::lock_exclusive(&file)?;
+                    }
+                }
+            }
+            Err(e) => return Err(e).chain_err(|| format!("failed to lock {}", path.display())),
+        }
+        debug!("locked {}", path.display());
+        Ok(Some(PackLock { file: file }))
+    }
+}
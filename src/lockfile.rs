@@ -0,0 +1,101 @@
+//! `pack.lock`, the digests of the source files recorded to catch unintended changes
+
+use {CargoPack, EntrySource, PackEntry};
+use archive::entry_digest;
+use error::*;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// returns the sha256 digest of each resolved source file by its destination
+fn source_digests(cargo_pack: &CargoPack) -> Result<BTreeMap<String, String>> {
+    let mut digests = BTreeMap::new();
+    for (src, dest) in cargo_pack.resolved_files_with_dest()? {
+        let entry = PackEntry {
+            source: EntrySource::Path(src),
+            dest: dest,
+            mode: None,
+        };
+        digests.insert(entry.dest.to_string_lossy().replace('\\', "/"), entry_digest(&entry)?);
+    }
+    Ok(digests)
+}
+
+/// parses the lock file `content` of `path`
+fn parse(path: &Path, content: &str) -> Result<BTreeMap<String, String>> {
+    let mut digests = BTreeMap::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(2, "  ");
+        match (fields.next(), fields.next()) {
+            (Some(digest), Some(dest)) if digest.len() == 64 && !dest.is_empty() => {
+                digests.insert(dest.to_string(), digest.to_string());
+            }
+            _ => {
+                return Err(format!(
+                    "{}:{}: invalid line, expected `<sha256>  <path>`",
+                    path.display(),
+                    i + 1
+                ).into())
+            }
+        }
+    }
+    Ok(digests)
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// writes the sha256 digests of the files resolved from `files` into the lock file `path`,
+    /// for `verify_against_lock` to check them later.
+    ///
+    /// The lock file has a line `<sha256 in hex>  <destination>` for each file, sorted by the
+    /// destination, following a `#` comment line. It is the format of `sha256sum` with
+    /// destinations in place of file names. Binaries and generated files are not recorded, as
+    /// they change on each build.
+    pub fn write_lock(&self, path: &Path) -> Result<()> {
+        let mut content = String::from("# sha256 digests of the files packed by cargo-pack\n");
+        for (dest, digest) in source_digests(self)? {
+            content.push_str(&format!("{}  {}\n", digest, dest));
+        }
+        File::create(path)
+            .and_then(|mut f| f.write_all(content.as_bytes()))
+            .chain_err(|| format!("failed to write {}", path.display()))
+    }
+
+    /// checks that the files resolved from `files` are those recorded by `write_lock` in `path`.
+    ///
+    /// The error lists every difference: files whose content changed, files in the lock which
+    /// are no longer packed and files packed which are not in the lock.
+    pub fn verify_against_lock(&self, path: &Path) -> Result<()> {
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut content))
+            .chain_err(|| format!("failed to read {}", path.display()))?;
+        let locked = parse(path, &content)?;
+        let current = source_digests(self)?;
+        let mut problems = Vec::new();
+        for (dest, digest) in &current {
+            match locked.get(dest) {
+                Some(locked_digest) if locked_digest != digest => problems.push(format!("changed: {}", dest)),
+                Some(_) => (),
+                None => problems.push(format!("new: {}", dest)),
+            }
+        }
+        for dest in locked.keys() {
+            if !current.contains_key(dest) {
+                problems.push(format!("missing: {}", dest));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "files to pack do not match {}:\n  {}",
+                path.display(),
+                problems.join("\n  ")
+            ).into())
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! resolving a package from the output of `cargo metadata`, without loading the workspace
+
+use {PackConfig, default_profile, profile_dir_name, resolve_files};
+use error::*;
+use serde_json::{self, Value};
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<MetadataPackage>,
+    workspace_members: Vec<String>,
+    target_directory: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct MetadataPackage {
+    id: String,
+    name: String,
+    version: String,
+    manifest_path: PathBuf,
+    targets: Vec<MetadataTarget>,
+    #[serde(default)]
+    metadata: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct MetadataTarget {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// a package read from the JSON printed by `cargo metadata --no-deps`, for tools which run cargo
+/// themselves. Unlike `CargoPack`, no workspace is loaded: the package, its binary targets, its
+/// `package.metadata.pack` and the target directory all come from the metadata.
+///
+/// Files are resolved as `resolve_files` does, so entries needing the workspace, like those of
+/// other members or with a named `base` other than `@package`, are errors. So are entries with
+/// `cfg`, as they need rustc. The `os` section of the host is applied but `target` sections are not.
+#[derive(Debug)]
+pub struct MetadataPack {
+    name: String,
+    version: String,
+    manifest_path: PathBuf,
+    target_directory: PathBuf,
+    bins: Vec<String>,
+    pack_config: PackConfig,
+}
+
+impl MetadataPack {
+    /// finds the package `package_name` among the workspace members of the metadata `json`.
+    /// Without `package_name`, the workspace must have exactly one member.
+    ///
+    /// These fields are required:
+    ///
+    /// - `workspace_members`
+    /// - `target_directory`
+    /// - `packages[].id`, `packages[].name`, `packages[].version` and `packages[].manifest_path`
+    /// - `packages[].targets[].name` and `packages[].targets[].kind`
+    ///
+    /// `packages[].metadata` is optional. Without its `pack` key, the config is the default one.
+    pub fn from_metadata_json(json: &str, package_name: Option<String>) -> Result<Self> {
+        let metadata: Metadata = serde_json::from_str(json).chain_err(|| "failed to parse cargo metadata")?;
+        let workspace_members = metadata.workspace_members;
+        let mut members = metadata
+            .packages
+            .into_iter()
+            .filter(|p| workspace_members.contains(&p.id))
+            .collect::<Vec<_>>();
+        let index = match package_name {
+            Some(ref name) => {
+                let found = members
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, p)| p.name == *name)
+                    .map(|(i, _)| i)
+                    .collect::<Vec<_>>();
+                match found.len() {
+                    0 => return Err(format!("unknown package {}", name).into()),
+                    1 => found[0],
+                    _ => return Err(format!("ambiguous name {}", name).into()),
+                }
+            }
+            None => match members.len() {
+                1 => 0,
+                _ => return Err("the workspace has multiple members. specify the package name".into()),
+            },
+        };
+        let package = members.swap_remove(index);
+        let mut pack_config = match package.metadata.as_ref().and_then(|m| m.get("pack")) {
+            Some(pack) => serde_json::from_value(pack.clone())
+                .chain_err(|| format!("failed to decode package.metadata.pack of {}", package.name))?,
+            None => PackConfig::default(),
+        };
+        pack_config.merge_host_os();
+        Ok(MetadataPack {
+            bins: package
+                .targets
+                .iter()
+                .filter(|t| t.kind.iter().any(|k| k == "bin"))
+                .map(|t| t.name.clone())
+                .collect(),
+            name: package.name,
+            version: package.version,
+            manifest_path: package.manifest_path,
+            target_directory: metadata.target_directory,
+            pack_config: pack_config,
+        })
+    }
+
+    /// returns the name of the package
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// returns the version of the package
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    /// returns the directory of the manifest of the package
+    pub fn root(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or_else(|| Path::new(""))
+    }
+
+    /// returns `package.metadata.pack` of the package
+    pub fn pack_config(&self) -> &PackConfig {
+        &self.pack_config
+    }
+
+    /// returns the names of the binary targets of the package, in the order of the metadata
+    pub fn binary_names(&self) -> &[String] {
+        &self.bins
+    }
+
+    /// returns the binaries built with `profile`, or the default profile, in `target_directory`,
+    /// only those named in `bins` of the config if given. It is an error if one is not built.
+    pub fn binaries<'a, P: Into<Option<&'a str>>>(&self, profile: P) -> Result<Vec<PathBuf>> {
+        let profile = profile.into().map(ToString::to_string).unwrap_or_else(default_profile);
+        if let Some(ref bins) = self.pack_config.bins {
+            for name in bins {
+                if !self.bins.contains(name) {
+                    return Err(format!("bins: `{}` is not a binary target of {}", name, self.name).into());
+                }
+            }
+        }
+        let dir = self.target_directory.join(profile_dir_name(&profile));
+        let mut binaries = Vec::new();
+        for name in &self.bins {
+            if let Some(ref bins) = self.pack_config.bins {
+                if !bins.contains(name) {
+                    continue;
+                }
+            }
+            let path = dir.join(format!("{}{}", name, env::consts::EXE_SUFFIX));
+            if !path.is_file() {
+                return Err(format!(
+                    "binary {} not found. build it with profile {} first",
+                    path.display(),
+                    profile
+                ).into());
+            }
+            binaries.push(path);
+        }
+        Ok(binaries)
+    }
+
+    /// resolves `files` under the package root as `resolve_files` does
+    pub fn resolved_files_with_dest(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        resolve_files(self.root(), &self.pack_config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataPack;
+    use serde_json;
+    use std::path::PathBuf;
+    use test_util::TempDir;
+
+    fn metadata(dir: &TempDir, pack: &str) -> String {
+        format!(
+            r#"{{
+                "packages": [
+                    {{
+                        "id": "foo 0.1.0 (path+file:///foo)",
+                        "name": "foo",
+                        "version": "0.1.0",
+                        "manifest_path": {},
+                        "targets": [
+                            {{ "name": "foo", "kind": ["bin"] }},
+                            {{ "name": "foo-tool", "kind": ["bin"] }},
+                            {{ "name": "foo", "kind": ["lib"] }}
+                        ],
+                        "metadata": {{ "pack": {} }}
+                    }},
+                    {{
+                        "id": "dep 1.0.0 (registry+https://github.com/rust-lang/crates.io-index)",
+                        "name": "dep",
+                        "version": "1.0.0",
+                        "manifest_path": "/registry/dep/Cargo.toml",
+                        "targets": []
+                    }}
+                ],
+                "workspace_members": ["foo 0.1.0 (path+file:///foo)"],
+                "target_directory": {}
+            }}"#,
+            serde_json::to_string(&dir.path().join("Cargo.toml")).unwrap(),
+            pack,
+            serde_json::to_string(&dir.path().join("target")).unwrap()
+        )
+    }
+
+    #[test]
+    fn resolves_the_package_from_the_metadata() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        dir.binary("foo");
+        dir.binary("foo-tool");
+        let pack = MetadataPack::from_metadata_json(&metadata(&dir, r#"{"files": ["README.md"]}"#), None).unwrap();
+        assert_eq!(pack.name(), "foo");
+        assert_eq!(pack.binary_names(), &["foo".to_string(), "foo-tool".to_string()]);
+        assert_eq!(
+            pack.resolved_files_with_dest().unwrap(),
+            vec![(dir.path().join("README.md"), PathBuf::from("README.md"))]
+        );
+        assert_eq!(pack.binaries("release").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn unknown_package_is_an_error() {
+        let dir = TempDir::new();
+        let e = MetadataPack::from_metadata_json(&metadata(&dir, "{}"), Some("dep".to_string()))
+            .err()
+            .unwrap();
+        assert_eq!(e.to_string(), "unknown package dep");
+    }
+}
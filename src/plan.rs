@@ -0,0 +1,444 @@
+//! the resolved content of a package, to inspect before writing anything
+
+use {CargoPack, CaseCollisions, EntrySource, FileEntry, PackEntry, binary_target_name, default_profile, find_member,
+     is_inside};
+use archive::entry_digest;
+use error::*;
+use serde_json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// paths longer than this are truncated by `CargoPack::format_plan`
+const MAX_PATH_WIDTH: usize = 60;
+
+/// version of the JSON written by `PackPlan::to_json`, its `schema_version`. Bumped when a field
+/// is removed or changes its meaning. Fields may be added without bumping it.
+pub const PACK_JSON_SCHEMA_VERSION: u32 = 1;
+
+/// everything an archive of the package contains. Created by `CargoPack::plan`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackPlan {
+    /// the binaries and the entries, sorted by the destination
+    pub entries: Vec<PlanEntry>,
+}
+
+/// an entry of `PackPlan`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanEntry {
+    /// the entry to pack
+    pub entry: PackEntry,
+    /// size of the content in bytes
+    pub size: u64,
+    /// whether the entry is a binary of the package rather than a file of `files`
+    pub binary: bool,
+}
+
+/// the JSON form of `PackPlan`, written by `PackPlan::to_json`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlanJson {
+    /// `PACK_JSON_SCHEMA_VERSION` of the crate which wrote it
+    pub schema_version: u32,
+    /// the entries, sorted by the destination
+    pub entries: Vec<PlanJsonEntry>,
+    /// total size in bytes of the contents
+    pub total_size: u64,
+}
+
+/// an entry of `PlanJson`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlanJsonEntry {
+    /// absolute path of the source file, `null` for generated files
+    pub source: Option<PathBuf>,
+    /// path in the package
+    pub dest: PathBuf,
+    /// size of the content in bytes
+    pub size: u64,
+    /// whether the entry is a binary of the package
+    pub binary: bool,
+    /// permission bits to use instead of the source's, if any
+    pub mode: Option<u32>,
+}
+
+/// differences between two plans by destination. Created by `PackPlan::diff`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlanDiff {
+    /// destinations only in the new plan, sorted
+    pub added: Vec<PathBuf>,
+    /// destinations only in the old plan, sorted
+    pub removed: Vec<PathBuf>,
+    /// destinations in both plans with different contents, sorted
+    pub changed: Vec<PathBuf>,
+}
+
+impl PlanDiff {
+    /// returns whether the plans have the same contents
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// returns whether `old` and `new` have a different content. Sizes are compared first and the
+/// sha256 digests only when both can be read.
+fn is_changed(old: &PlanEntry, new: &PlanEntry) -> bool {
+    if old.size != new.size {
+        return true;
+    }
+    match (entry_digest(&old.entry), entry_digest(&new.entry)) {
+        (Ok(old), Ok(new)) => old != new,
+        _ => false,
+    }
+}
+
+impl PackPlan {
+    /// returns the total size in bytes of the contents
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+
+    /// converts the plan into its JSON form, which is a stable format for other tools to read.
+    pub fn to_plan_json(&self) -> PlanJson {
+        PlanJson {
+            schema_version: PACK_JSON_SCHEMA_VERSION,
+            entries: self.entries
+                .iter()
+                .map(|e| PlanJsonEntry {
+                    source: match e.entry.source {
+                        EntrySource::Path(ref src) => Some(src.clone()),
+                        EntrySource::Content(_) => None,
+                    },
+                    dest: e.entry.dest.clone(),
+                    size: e.size,
+                    binary: e.binary,
+                    mode: e.entry.mode,
+                })
+                .collect(),
+            total_size: self.total_size(),
+        }
+    }
+
+    /// writes the plan as JSON of `PlanJson`. The `schema_version` field tells the version of
+    /// the format, `PACK_JSON_SCHEMA_VERSION`.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.to_plan_json()).chain_err(|| "failed to write the plan as JSON")
+    }
+
+    /// compares this plan, the old one, to `other`, the new one.
+    ///
+    /// Contents are compared by size, and by digest when the files of both entries can still be
+    /// read. Where they cannot, e.g. the old plan was made from a removed checkout, entries of the
+    /// same size count as unchanged.
+    pub fn diff(&self, other: &PackPlan) -> PlanDiff {
+        let old = self.entries.iter().map(|e| (&e.entry.dest, e)).collect::<BTreeMap<_, _>>();
+        let new = other.entries.iter().map(|e| (&e.entry.dest, e)).collect::<BTreeMap<_, _>>();
+        let mut diff = PlanDiff::default();
+        for (dest, new_entry) in &new {
+            match old.get(dest) {
+                None => diff.added.push(dest.to_path_buf()),
+                Some(old_entry) if is_changed(old_entry, new_entry) => diff.changed.push(dest.to_path_buf()),
+                Some(_) => (),
+            }
+        }
+        for dest in old.keys() {
+            if !new.contains_key(dest) {
+                diff.removed.push(dest.to_path_buf());
+            }
+        }
+        diff
+    }
+}
+
+fn content_size(entry: &PackEntry) -> Result<u64> {
+    match entry.source {
+        EntrySource::Path(ref src) => Ok(fs::metadata(src)
+            .chain_err(|| format!("failed to read metadata of {}", src.display()))?
+            .len()),
+        EntrySource::Content(ref content) => Ok(content.len() as u64),
+    }
+}
+
+/// shortens `s` to `width` characters, keeping the end which is the most telling part of a path
+fn truncate(s: &str, width: usize) -> String {
+    let len = s.chars().count();
+    if len <= width {
+        s.to_string()
+    } else {
+        let tail = s.chars().skip(len - (width - 3)).collect::<String>();
+        format!("...{}", tail)
+    }
+}
+
+/// fails if a destination of `entries` is not a relative path inside the archive, so that no
+/// writer places a file outside of where it writes
+fn check_destinations(entries: &[PlanEntry]) -> Result<()> {
+    let e = match entries.iter().find(|e| !is_inside(&e.entry.dest)) {
+        Some(e) => e,
+        None => return Ok(()),
+    };
+    let source = match e.entry.source {
+        EntrySource::Path(ref src) => src.display().to_string(),
+        EntrySource::Content(_) => "a generated file".to_string(),
+    };
+    Err(format!(
+        "the destination {} of {} must be a relative path without `..`",
+        e.entry.dest.display(),
+        source
+    ).into())
+}
+
+/// returns the pairs of destinations of `entries` differing only by case, in the order of the
+/// entries
+fn case_collisions(entries: &[PlanEntry]) -> Vec<(&Path, &Path)> {
+    let mut seen: HashMap<String, &Path> = HashMap::new();
+    let mut collisions = Vec::new();
+    for e in entries {
+        let dest = e.entry.dest.as_path();
+        let other = *seen.entry(dest.to_string_lossy().to_lowercase()).or_insert(dest);
+        if other != dest {
+            collisions.push((other, dest));
+        }
+    }
+    collisions
+}
+
+/// a binary packed: the package, the binary target and the destination
+pub(crate) type PackedBinary = (String, String, PathBuf);
+
+fn packed_binary(package: &str, entry: PackEntry) -> PackedBinary {
+    let file_name = match entry.source {
+        EntrySource::Path(ref src) => src.file_name().map(|n| n.to_string_lossy().into_owned()),
+        EntrySource::Content(_) => None,
+    }.unwrap_or_default();
+    (package.to_string(), binary_target_name(&file_name).to_string(), entry.dest)
+}
+
+/// returns the destinations more than one of `binaries` are packed at, with the packages and the
+/// binary targets packed there
+pub(crate) fn binary_collisions(binaries: Vec<PackedBinary>) -> Vec<(PathBuf, Vec<(String, String)>)> {
+    let mut by_dest: BTreeMap<PathBuf, Vec<(String, String)>> = BTreeMap::new();
+    for (package, binary, dest) in binaries {
+        let packed = by_dest.entry(dest).or_insert_with(Vec::new);
+        if !packed.contains(&(package.clone(), binary.clone())) {
+            packed.push((package, binary));
+        }
+    }
+    by_dest.into_iter().filter(|&(_, ref packed)| packed.len() > 1).collect()
+}
+
+/// returns the error telling `collisions` of `binary_collisions`
+pub(crate) fn collisions_error(collisions: &[(PathBuf, Vec<(String, String)>)]) -> Error {
+    let collisions = collisions
+        .iter()
+        .map(|&(ref dest, ref packed)| {
+            let packed = packed
+                .iter()
+                .map(|&(ref package, ref binary)| format!("{}/{}", package, binary))
+                .collect::<Vec<_>>();
+            format!("{}: {}", dest.display(), packed.join(", "))
+        })
+        .collect::<Vec<_>>();
+    format!(
+        "binaries are packed at the same destination:\n  {}\nrename them with rename-binaries, \
+         like `\"<package>/<binary>\" = \"<new name>\"`",
+        collisions.join("\n  ")
+    ).into()
+}
+
+/// fails if binaries of `packs`, the members of a batch, are packed at the same destination.
+/// Only the members with `check-binary-collisions` enabled take part.
+pub(crate) fn check_batch_binary_collisions(packs: &[CargoPack]) -> Result<()> {
+    let mut binaries = Vec::new();
+    for pack in packs.iter().filter(|pack| pack.checks_binary_collisions()) {
+        binaries.extend(pack.packed_binaries()?);
+    }
+    let collisions = binary_collisions(binaries);
+    if collisions.is_empty() {
+        Ok(())
+    } else {
+        Err(collisions_error(&collisions))
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// fails or warns about the destinations of `plan` differing only by case, as `case-collisions`
+    /// tells
+    fn check_case_collisions(&self, plan: &PackPlan) -> Result<()> {
+        let collisions = case_collisions(&plan.entries)
+            .into_iter()
+            .map(|(a, b)| format!("{} and {}", a.display(), b.display()))
+            .collect::<Vec<_>>();
+        if collisions.is_empty() {
+            return Ok(());
+        }
+        match self.pack_config.case_collisions.unwrap_or(CaseCollisions::Error) {
+            CaseCollisions::Error => Err(format!(
+                "destinations collide on case-insensitive file systems:\n  {}",
+                collisions.join("\n  ")
+            ).into()),
+            CaseCollisions::Warn => {
+                for collision in collisions {
+                    warn!("destinations collide on case-insensitive file systems: {}", collision);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// returns whether `check-binary-collisions` is enabled
+    pub(crate) fn checks_binary_collisions(&self) -> bool {
+        self.pack_config.check_binary_collisions.unwrap_or(false)
+    }
+
+    /// returns the binaries packed: those of the package and of the members packed with
+    /// `artifact = "bin"`
+    pub(crate) fn packed_binaries(&self) -> Result<Vec<PackedBinary>> {
+        let package = self.package()?;
+        let mut binaries = Vec::new();
+        if self.include_binaries {
+            for entry in self.binary_entries(None)? {
+                binaries.push(packed_binary(package.name(), entry));
+            }
+        }
+        for entry in self.files() {
+            if let FileEntry::MemberArtifact {
+                package: ref member,
+                ref artifact,
+            } = *entry
+            {
+                if artifact != "bin" {
+                    continue;
+                }
+                let member = find_member(self.ws(), member)?;
+                for entry in self.binary_entries_of(member, &default_profile(), None)? {
+                    binaries.push(packed_binary(member.name(), entry));
+                }
+            }
+        }
+        Ok(binaries)
+    }
+
+    /// fails if binaries of different packages or targets are packed at the same destination
+    /// when `check-binary-collisions` is enabled. The binaries are those of `packed_binaries`.
+    fn check_binary_collisions(&self) -> Result<()> {
+        if !self.checks_binary_collisions() {
+            return Ok(());
+        }
+        let collisions = binary_collisions(self.packed_binaries()?);
+        if collisions.is_empty() {
+            Ok(())
+        } else {
+            Err(collisions_error(&collisions))
+        }
+    }
+
+    /// resolves the binaries and `entries` into a plan without writing anything.
+    /// The binaries are left out with `CargoPackBuilder::with_include_binaries(false)`.
+    /// It is an error if a destination is absolute or has `..`, if they are larger than
+    /// `max-total-size` in total, or if destinations differ only by case unless `case-collisions`
+    /// is `warn`, or if binaries collide with `check-binary-collisions`.
+    pub fn plan(&self) -> Result<PackPlan> {
+        let mut entries = Vec::new();
+        if self.include_binaries {
+            for entry in self.binary_entries(None)? {
+                entries.push(PlanEntry {
+                    size: content_size(&entry)?,
+                    entry: entry,
+                    binary: true,
+                });
+            }
+        }
+        for entry in self.entries()? {
+            entries.push(PlanEntry {
+                size: content_size(&entry)?,
+                entry: entry,
+                binary: false,
+            });
+        }
+        entries.sort_by(|a, b| a.entry.dest.cmp(&b.entry.dest));
+        check_destinations(&entries)?;
+        let plan = PackPlan { entries: entries };
+        self.check_total_size(plan.total_size())?;
+        self.check_case_collisions(&plan)?;
+        self.check_binary_collisions()?;
+        Ok(plan)
+    }
+
+    /// formats `plan` as a table of sources and destinations for a human to preview.
+    ///
+    /// Each line is a source and its destination, with binaries marked `bin`. Sources are relative
+    /// to the workspace root, generated files are shown as `(generated)` and paths longer than
+    /// 60 characters are cut at the front. The last line sums up the number of files and their size.
+    /// The output only depends on the plan and the workspace root.
+    pub fn format_plan(&self, plan: &PackPlan) -> String {
+        let root = self.ws().root();
+        let rows = plan.entries
+            .iter()
+            .map(|e| {
+                let source = match e.entry.source {
+                    EntrySource::Path(ref src) => src.strip_prefix(root).unwrap_or(src).display().to_string(),
+                    EntrySource::Content(_) => "(generated)".to_string(),
+                };
+                (
+                    if e.binary { "bin" } else { "" },
+                    truncate(&source, MAX_PATH_WIDTH),
+                    truncate(&e.entry.dest.display().to_string(), MAX_PATH_WIDTH),
+                )
+            })
+            .collect::<Vec<_>>();
+        let width = rows.iter().map(|&(_, ref src, _)| src.chars().count()).max().unwrap_or(0);
+        let mut out = String::new();
+        for (kind, source, dest) in rows {
+            out.push_str(&format!("{:3} {:width$} -> {}\n", kind, source, dest, width = width));
+        }
+        let binaries = plan.entries.iter().filter(|e| e.binary).count();
+        out.push_str(&format!(
+            "{} files ({} binaries), {} bytes\n",
+            plan.entries.len(),
+            binaries,
+            plan.total_size()
+        ));
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_util::{self, TempDir};
+
+    #[test]
+    fn binaries_are_left_out_without_include_binaries() {
+        let dir = TempDir::new();
+        dir.write("assets/logo.png", "png");
+        dir.binary("foo");
+        let manifest = dir.package(".", "foo", "[package.metadata.pack]\nfiles = [\"assets/*\"]");
+        let config = test_util::config();
+        let dests = |include| {
+            let pack = test_util::builder(&config, &dir, &manifest)
+                .with_include_binaries(include)
+                .build()
+                .unwrap();
+            let entries = pack.plan().unwrap().entries.into_iter().map(|e| e.entry).collect::<Vec<_>>();
+            test_util::dests(&entries)
+        };
+        assert_eq!(dests(true), vec!["assets/logo.png", "foo"]);
+        assert_eq!(dests(false), vec!["assets/logo.png"]);
+    }
+
+    #[test]
+    fn destinations_differing_only_by_case_collide() {
+        let dir = TempDir::new();
+        dir.write("docs/README.md", "readme");
+        dir.write("docs/Readme.md", "readme");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"docs/*\"]");
+        let e = pack.plan().err().unwrap();
+        assert_eq!(
+            e.to_string(),
+            "destinations collide on case-insensitive file systems:\n  docs/README.md and docs/Readme.md"
+        );
+
+        let pack = test_util::pack(&config, &dir, "files = [\"docs/*\"]\ncase-collisions = \"warn\"");
+        assert_eq!(pack.plan().unwrap().entries.len(), 3);
+    }
+}
@@ -0,0 +1,53 @@
+//! checks whether a package is ready to be released
+
+use CargoPack;
+use error::*;
+
+impl<'cfg> CargoPack<'cfg> {
+    /// checks that the selected package is ready to be released. The checks are:
+    ///
+    /// - `package.description` is not empty
+    /// - `package.license` or `package.license-file` is set
+    /// - `package.license-file` and `package.readme` exist if set
+    /// - every entry of `files` matches some file
+    /// - there is at least one binary or file to pack
+    ///
+    /// Binaries need not be built yet. The error lists every failed check.
+    pub fn preflight(&self) -> Result<()> {
+        let package = self.package()?;
+        let root = package.root();
+        let metadata = package.manifest().metadata();
+        let mut problems = Vec::new();
+        if metadata.description.as_ref().map_or(true, |d| d.trim().is_empty()) {
+            problems.push("package.description is empty".to_string());
+        }
+        if metadata.license.is_none() && metadata.license_file.is_none() {
+            problems.push("neither package.license nor package.license-file is set".to_string());
+        }
+        for &(key, path) in &[
+            ("license-file", &metadata.license_file),
+            ("readme", &metadata.readme),
+        ] {
+            if let Some(ref path) = *path {
+                if !root.join(path).is_file() {
+                    problems.push(format!("package.{}: {} does not exist", key, path));
+                }
+            }
+        }
+        if let Err(e) = self.pack_config.validate(root) {
+            problems.push(e.to_string());
+        }
+        let has_binaries = self.include_binaries && package
+            .targets()
+            .iter()
+            .any(|t| t.is_bin() && self.bins().map_or(true, |bins| bins.iter().any(|b| b == t.name())));
+        if !has_binaries && self.files().is_empty() {
+            problems.push("nothing to pack: no binary targets and no files".to_string());
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{} is not ready to release:\n  {}", package.name(), problems.join("\n  ")).into())
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! resolving a package with the advisories a frontend shows apart from errors
+
+use {CargoPack, EntrySource, FileEntry};
+use error::*;
+use glob;
+use plan::PackPlan;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// a problem found resolving a package which does not stop packing it.
+/// Returned by `CargoPack::resolve_with_report`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// a file to pack is larger than `warn-file-size`
+    LargeFile {
+        /// the source file
+        path: PathBuf,
+        /// size of the file in bytes
+        size: u64,
+        /// `warn-file-size`
+        limit: u64,
+    },
+    /// a file the manifest refers to, `package.readme` or `package.license-file`, does not exist
+    MissingManifestFile {
+        /// the key of the manifest, like `readme`
+        key: &'static str,
+        /// the missing path, relative to the package root
+        path: PathBuf,
+    },
+    /// a glob pattern of `files` relative to the package root matched a file in the target
+    /// directory. Such files are usually build outputs swept in by a broad pattern like `**/*.txt`.
+    /// Entries with a `base` are not checked as they name their directory on purpose.
+    InTargetDir {
+        /// the pattern
+        pattern: String,
+        /// the source file
+        path: PathBuf,
+    },
+}
+
+/// the files to pack which cannot be read, found by `CargoPack::validate_files`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileValidation {
+    /// files named in `files` which do not exist, sorted
+    pub missing: Vec<PathBuf>,
+    /// files which exist but cannot be opened for lack of permission, sorted
+    pub unreadable: Vec<PathBuf>,
+}
+
+impl FileValidation {
+    /// returns whether every file can be packed
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unreadable.is_empty()
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::LargeFile {
+                ref path,
+                size,
+                limit,
+            } => write!(
+                f,
+                "{} is {} bytes, larger than warn-file-size {}",
+                path.display(),
+                size,
+                limit
+            ),
+            Warning::MissingManifestFile { key, ref path } => {
+                write!(f, "package.{}: {} does not exist", key, path.display())
+            }
+            Warning::InTargetDir {
+                ref pattern,
+                ref path,
+            } => write!(f, "{} matched {} in the target directory", pattern, path.display()),
+        }
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// resolves the package into a plan as `plan` does, collecting the problems which do not
+    /// stop packing it as warnings instead of only logging them.
+    ///
+    /// Errors of `plan` are still returned as `Err`. The warnings are in the order of the
+    /// manifest files, the large files in the plan and the entries of `files`.
+    pub fn resolve_with_report(&self) -> Result<(PackPlan, Vec<Warning>)> {
+        let plan = self.plan()?;
+        let package = self.package()?;
+        let metadata = package.manifest().metadata();
+        let mut warnings = Vec::new();
+        for &(key, path) in &[
+            ("readme", &metadata.readme),
+            ("license-file", &metadata.license_file),
+        ] {
+            if let Some(ref path) = *path {
+                if !package.root().join(path).is_file() {
+                    warnings.push(Warning::MissingManifestFile {
+                        key: key,
+                        path: PathBuf::from(path),
+                    });
+                }
+            }
+        }
+        if let Some(limit) = self.pack_config.warn_file_size {
+            for e in plan.entries.iter().filter(|e| !e.binary && e.size > limit) {
+                if let EntrySource::Path(ref src) = e.entry.source {
+                    warnings.push(Warning::LargeFile {
+                        path: src.clone(),
+                        size: e.size,
+                        limit: limit,
+                    });
+                }
+            }
+        }
+        let target_dir = self.target_dir();
+        let target_dir = target_dir.canonicalize().unwrap_or(target_dir);
+        let mut tracked = None;
+        for (index, entry) in self.files().iter().enumerate() {
+            let pattern = match *entry {
+                FileEntry::Pattern(ref pattern) if entry.is_glob() && entry.negated().is_none() => pattern,
+                _ => continue,
+            };
+            for e in self.expand_at(package, index, false, None, &mut tracked)? {
+                if let EntrySource::Path(ref src) = e.source {
+                    if src.canonicalize().map(|src| src.starts_with(&target_dir)).unwrap_or(false) {
+                        warnings.push(Warning::InTargetDir {
+                            pattern: pattern.clone(),
+                            path: src.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        Ok((plan, warnings))
+    }
+
+    /// lists the files under `dir` which the plan does not pack, sorted, to tell why a file is
+    /// missing from the archive. Hidden files are listed too.
+    ///
+    /// Unless `dir` is in the target directory, files in the target directory are left out as
+    /// they are build outputs. The binaries must be built, as `plan` requires.
+    pub fn unmatched_in_dir(&self, dir: &Path) -> Result<Vec<PathBuf>> {
+        let dir = dir.canonicalize()
+            .chain_err(|| format!("failed to read {}", dir.display()))?;
+        let dir_str = dir.to_str()
+            .ok_or_else(|| format!("non UTF-8 path {:?}", dir))?;
+        let packed = self.plan()?
+            .entries
+            .into_iter()
+            .filter_map(|e| match e.entry.source {
+                EntrySource::Path(src) => Some(src.canonicalize().unwrap_or(src)),
+                EntrySource::Content(_) => None,
+            })
+            .collect::<HashSet<_>>();
+        let target_dir = self.target_dir();
+        let target_dir = target_dir.canonicalize().unwrap_or(target_dir);
+        let skip_target_dir = !dir.starts_with(&target_dir);
+        let mut unmatched = Vec::new();
+        for path in glob::glob(&format!("{}/**/*", glob::Pattern::escape(dir_str)))? {
+            let path = path?;
+            if !path.is_file() || packed.contains(&path) || (skip_target_dir && path.starts_with(&target_dir)) {
+                continue;
+            }
+            unmatched.push(path);
+        }
+        unmatched.sort();
+        Ok(unmatched)
+    }
+
+    /// checks that the files of `files` can be read, telling the files which do not exist from
+    /// those which cannot be opened for lack of permission.
+    ///
+    /// Files named without a glob, and the `from` of renamed files, are missing when they do not
+    /// exist. Every file matched by the other entries is opened to find the unreadable ones.
+    /// Other errors resolving the entries, like a glob matching nothing, are returned as `Err`.
+    pub fn validate_files(&self) -> Result<FileValidation> {
+        let package = self.package()?;
+        let mut validation = FileValidation::default();
+        let check = |path: PathBuf, validation: &mut FileValidation| match File::open(&path) {
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                validation.missing.push(path);
+                Ok(())
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                validation.unreadable.push(path);
+                Ok(())
+            }
+            Err(e) => Err(Error::with_chain(e, format!("failed to read {}", path.display()))),
+        };
+        let mut tracked = None;
+        for (index, entry) in self.files().iter().enumerate() {
+            let literal = match *entry {
+                FileEntry::Pattern(ref pattern) if !entry.is_glob() && entry.negated().is_none() => {
+                    let pattern = if pattern.starts_with("\\!") { &pattern[1..] } else { pattern };
+                    Some(package.root().join(pattern))
+                }
+                FileEntry::Rename { ref from, .. } => Some(self.real_path(package.root(), Path::new(from))),
+                _ => None,
+            };
+            match literal {
+                Some(path) => {
+                    if fs::metadata(&path).map(|meta| meta.is_file()).unwrap_or(true) {
+                        check(path, &mut validation)?;
+                    }
+                }
+                None => for e in self.expand_at(package, index, false, None, &mut tracked)? {
+                    if let EntrySource::Path(src) = e.source {
+                        check(src, &mut validation)?;
+                    }
+                },
+            }
+        }
+        validation.missing.sort();
+        validation.missing.dedup();
+        validation.unreadable.sort();
+        validation.unreadable.dedup();
+        Ok(validation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileValidation, Warning};
+    use std::path::PathBuf;
+    use test_util::{self, TempDir};
+
+    #[test]
+    fn files_larger_than_warn_file_size_are_warned() {
+        let dir = TempDir::new();
+        let large = dir.write("large.txt", "0123456789");
+        dir.write("small.txt", "0");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"*.txt\"]\nwarn-file-size = 5");
+        let (plan, warnings) = pack.resolve_with_report().unwrap();
+        assert_eq!(plan.entries.len(), 3);
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::LargeFile {
+                    path: large,
+                    size: 10,
+                    limit: 5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_manifest_files_and_files_in_the_target_dir_are_warned() {
+        let dir = TempDir::new();
+        dir.write("logs/app.log", "log");
+        let build = dir.write("target/release/build.log", "log");
+        dir.binary("foo");
+        let manifest = dir.package(
+            ".",
+            "foo",
+            "readme = \"README.md\"\n\n[package.metadata.pack]\nfiles = [\"**/*.log\"]",
+        );
+        let config = test_util::config();
+        let pack = test_util::builder(&config, &dir, &manifest).build().unwrap();
+        let (_, warnings) = pack.resolve_with_report().unwrap();
+        assert_eq!(
+            warnings,
+            vec![
+                Warning::MissingManifestFile {
+                    key: "readme",
+                    path: PathBuf::from("README.md"),
+                },
+                Warning::InTargetDir {
+                    pattern: "**/*.log".to_string(),
+                    path: build,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn unmatched_in_dir_lists_the_files_left_out() {
+        let dir = TempDir::new();
+        dir.write("dist/app.js", "js");
+        let map = dir.write("dist/app.js.map", "map");
+        let cache = dir.write("dist/.cache", "cache");
+        dir.write("target/release/build.log", "log");
+        dir.binary("foo");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"dist/*.js\"]");
+        assert_eq!(pack.unmatched_in_dir(&dir.path().join("dist")).unwrap(), vec![cache.clone(), map.clone()]);
+        assert_eq!(
+            pack.unmatched_in_dir(dir.path()).unwrap(),
+            vec![dir.path().join("Cargo.toml"), cache, map, dir.path().join("src/main.rs")]
+        );
+        assert_eq!(
+            pack.unmatched_in_dir(&dir.path().join("target")).unwrap(),
+            vec![dir.path().join("target/release/build.log")]
+        );
+    }
+
+    #[test]
+    fn validate_files_reports_missing_files() {
+        let dir = TempDir::new();
+        dir.write("README.md", "readme");
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"README.md\", \"NOTES.md\"]");
+        let validation = pack.validate_files().unwrap();
+        assert_eq!(
+            validation,
+            FileValidation {
+                missing: vec![dir.path().join("NOTES.md")],
+                unreadable: Vec::new(),
+            }
+        );
+        assert!(!validation.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn validate_files_reports_unreadable_files() {
+        use std::fs::{self, File};
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new();
+        let secret = dir.write("secret.txt", "secret");
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+        if File::open(&secret).is_ok() {
+            // running as root, which reads any file
+            return;
+        }
+        let config = test_util::config();
+        let pack = test_util::pack(&config, &dir, "files = [\"*.txt\"]");
+        assert_eq!(
+            pack.validate_files().unwrap(),
+            FileValidation {
+                missing: Vec::new(),
+                unreadable: vec![secret],
+            }
+        );
+    }
+}
@@ -0,0 +1,97 @@
+//! cfg expressions of the config: `target` sections and files entries with `cfg`, adding files
+//! when the target matches
+
+use {CargoPack, FileEntry, PackConfig};
+use cargo::util::{Cfg, CfgExpr};
+use error::*;
+use std::str::FromStr;
+
+/// parses `key`, a key of `target` or a `cfg` of files entries, like
+/// `cfg(all(target_os = "linux", target_arch = "x86_64"))`
+fn parse_key(key: &str) -> Result<CfgExpr> {
+    let expr = key.trim();
+    if !expr.starts_with("cfg(") || !expr.ends_with(')') {
+        return Err(format!("invalid target `{}`, expected cfg(<expression>)", key).into());
+    }
+    CfgExpr::from_str(&expr[4..expr.len() - 1]).chain_err(|| format!("invalid target `{}`", key))
+}
+
+/// returns the `cfg` of the entries with one among `files`
+fn entry_cfgs<'a>(files: &'a Option<Vec<FileEntry>>) -> Box<Iterator<Item = &'a str> + 'a> {
+    Box::new(files.iter().flat_map(|files| files).filter_map(|entry| match *entry {
+        FileEntry::Conditional { ref cfg, .. } => Some(cfg.as_str()),
+        _ => None,
+    }))
+}
+
+impl PackConfig {
+    /// checks that each key of `target` and each `cfg` of files entries is a cfg expression
+    pub(crate) fn check_target_cfgs(&self) -> Result<()> {
+        for key in self.target.iter().flat_map(|target| target.keys()) {
+            parse_key(key).chain_err(|| "invalid package.metadata.pack.target")?;
+        }
+        let sections = self.os.iter().chain(self.target.iter()).flat_map(|sections| sections.values());
+        for cfg in entry_cfgs(&self.files).chain(sections.flat_map(|section| entry_cfgs(&section.files))) {
+            parse_key(cfg).chain_err(|| "invalid cfg of package.metadata.pack.files")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'cfg> CargoPack<'cfg> {
+    /// returns the cfg of the target binaries are looked up for, or of the host, as rustc prints it.
+    /// There is no fallback: it is an error if rustc fails.
+    fn target_cfgs(&self) -> Result<Vec<Cfg>> {
+        let mut process = self.ws().config().rustc()?.process();
+        process.arg("--print=cfg");
+        if let Some(target) = self.target()? {
+            process.arg("--target").arg(target);
+        }
+        let output = process
+            .exec_with_output()
+            .chain_err(|| "failed to get the cfg of the target from rustc, needed by cfg expressions of the config")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut cfgs = Vec::new();
+        for line in stdout.lines().filter(|line| !line.trim().is_empty()) {
+            cfgs.push(Cfg::from_str(line).chain_err(|| format!("failed to parse the cfg `{}` of rustc", line))?);
+        }
+        Ok(cfgs)
+    }
+
+    /// appends `files` of the `target` sections of `pack_config` whose cfg expressions match the
+    /// target, in the order of the keys, to `files`. Then replaces the entries with `cfg` with
+    /// the entries they carry if the expressions match, or removes them. rustc is only run if
+    /// there are such sections or entries.
+    pub(crate) fn apply_target_cfgs(&self, pack_config: &mut PackConfig) -> Result<()> {
+        let has_sections = pack_config.target.as_ref().map_or(false, |target| !target.is_empty());
+        if !has_sections && entry_cfgs(&pack_config.files).next().is_none() {
+            return Ok(());
+        }
+        let cfgs = self.target_cfgs()?;
+        let mut files = Vec::new();
+        for (key, section) in pack_config.target.iter().flat_map(|target| target) {
+            if parse_key(key)?.matches(&cfgs) {
+                debug!("adding files for {}: {:?}", key, section.files);
+                files.extend(section.files.iter().flat_map(|f| f).cloned());
+            }
+        }
+        pack_config.add_files(files);
+        let files = match pack_config.files.take() {
+            Some(files) => files,
+            None => return Ok(()),
+        };
+        let mut kept = Vec::new();
+        for entry in files {
+            match entry {
+                FileEntry::Conditional { cfg, entry } => if parse_key(&cfg)?.matches(&cfgs) {
+                    kept.push(*entry);
+                } else {
+                    debug!("dropped a files entry for {}: {:?}", cfg, entry);
+                },
+                entry => kept.push(entry),
+            }
+        }
+        pack_config.files = Some(kept);
+        Ok(())
+    }
+}
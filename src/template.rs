@@ -0,0 +1,79 @@
+//! `{placeholder}` substitution in strings of the config
+
+use error::*;
+
+/// replaces each `{key}` in `template` with the value of `key` in `vars`.
+///
+/// `{{` and `}}` stand for literal braces. It is an error to use a placeholder not in `vars`.
+pub fn render(template: &str, vars: &[(&str, String)]) -> Result<String> {
+    let mut rendered = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                rendered.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                rendered.push('}');
+            }
+            '{' => {
+                let mut key = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => key.push(c),
+                        None => return Err(format!("unclosed placeholder in `{}`", template).into()),
+                    }
+                }
+                match vars.iter().find(|&&(name, _)| name == key) {
+                    Some(&(_, ref value)) => rendered.push_str(value),
+                    None => {
+                        let names = vars.iter().map(|&(name, _)| name).collect::<Vec<_>>();
+                        return Err(format!(
+                            "unknown placeholder `{{{}}}` in `{}`. known placeholders are {}",
+                            key,
+                            template,
+                            names.join(", ")
+                        ).into());
+                    }
+                }
+            }
+            '}' => return Err(format!("unmatched `}}` in `{}`", template).into()),
+            c => rendered.push(c),
+        }
+    }
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render;
+    use test_util::{self, TempDir};
+
+    #[test]
+    fn placeholders_are_replaced() {
+        let vars = vec![("name", "foo".to_string()), ("version", "0.1.0".to_string())];
+        assert_eq!(render("{name}-{version}/README", &vars).unwrap(), "foo-0.1.0/README");
+        assert_eq!(render("{{name}}", &vars).unwrap(), "{name}");
+        assert_eq!(
+            render("{nmae}", &vars).err().unwrap().to_string(),
+            "unknown placeholder `{nmae}` in `{nmae}`. known placeholders are name, version"
+        );
+        assert!(render("{name", &vars).is_err());
+    }
+
+    #[test]
+    fn destinations_of_renamed_files_are_rendered() {
+        let dir = TempDir::new();
+        dir.write("CHANGELOG.md", "changes");
+        let config = test_util::config();
+        let pack = test_util::pack(
+            &config,
+            &dir,
+            "files = [{ from = \"CHANGELOG.md\", to = \"{name}-{version}/CHANGELOG.md\" }]",
+        );
+        assert_eq!(test_util::dests(&pack.entries().unwrap()), vec!["foo-0.1.0/CHANGELOG.md"]);
+    }
+}
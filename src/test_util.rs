@@ -0,0 +1,98 @@
+//! helpers of the unit tests: packages written into temporary directories
+
+use {CargoPack, CargoPackBuilder};
+use cargo::util::Config;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+static COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// a directory removed when dropped
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "cargo-pack-test-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir {
+            path: path.canonicalize().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// writes `content` into `path` relative to the directory, creating the parents
+    pub fn write(&self, path: &str, content: &str) -> PathBuf {
+        let path = self.path.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path)
+            .and_then(|mut f| f.write_all(content.as_bytes()))
+            .unwrap();
+        path
+    }
+
+    /// writes a package `name` whose `Cargo.toml` ends with `extra` into `dir`, relative to the
+    /// directory
+    pub fn package(&self, dir: &str, name: &str, extra: &str) -> PathBuf {
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nauthors = []\n{}\n",
+            name, extra
+        );
+        self.write(&Path::new(dir).join("src/main.rs").to_string_lossy(), "fn main() {}\n");
+        self.write(&Path::new(dir).join("Cargo.toml").to_string_lossy(), &manifest)
+    }
+
+    /// writes a file standing for the release binary `name` into the target directory
+    pub fn binary(&self, name: &str) -> PathBuf {
+        self.write(
+            &format!("target/release/{}{}", name, env::consts::EXE_SUFFIX),
+            "binary",
+        )
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// returns the config of cargo to load the test packages with
+pub fn config() -> Config {
+    Config::default().unwrap()
+}
+
+/// returns a builder of the package of `manifest` using the target directory of `dir`
+pub fn builder<'cfg>(config: &'cfg Config, dir: &TempDir, manifest: &Path) -> CargoPackBuilder<'cfg> {
+    CargoPack::builder(config)
+        .with_manifest_path(manifest.to_path_buf())
+        .with_target_dir(dir.path().join("target"))
+}
+
+/// writes a package `foo` with `[package.metadata.pack]` of `pack` and builds it
+pub fn pack<'cfg>(config: &'cfg Config, dir: &TempDir, pack: &str) -> CargoPack<'cfg> {
+    let manifest = dir.package(".", "foo", &format!("[package.metadata.pack]\n{}", pack));
+    builder(config, dir, &manifest).build().unwrap()
+}
+
+
+/// returns the destinations of `entries`
+pub fn dests(entries: &[::PackEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .map(|e| e.dest.to_string_lossy().replace('\\', "/"))
+        .collect()
+}
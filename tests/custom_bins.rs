@@ -0,0 +1,41 @@
+extern crate cargo;
+extern crate cargo_pack;
+
+use cargo::util::Config;
+use cargo_pack::CargoPack;
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// the fixture package declaring `[[bin]]`s whose names differ from their source files
+fn manifest() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/custom-bins/Cargo.toml")
+}
+
+#[test]
+fn binaries_are_named_after_the_bin_targets() {
+    let target_dir = env::temp_dir().join(format!("cargo-pack-custom-bins-{}", process::id()));
+    let release = target_dir.join("release");
+    fs::create_dir_all(&release).unwrap();
+    for name in &["server", "cli-tool"] {
+        File::create(release.join(format!("{}{}", name, env::consts::EXE_SUFFIX))).unwrap();
+    }
+
+    let config = Config::default().unwrap();
+    let pack = CargoPack::builder(&config)
+        .with_manifest_path(manifest())
+        .with_target_dir(target_dir.clone())
+        .build()
+        .unwrap();
+    assert_eq!(pack.binary_names().unwrap(), vec!["server", "cli-tool"]);
+    let binaries = pack.binaries("release");
+    fs::remove_dir_all(&target_dir).unwrap();
+    assert_eq!(
+        binaries.unwrap(),
+        vec![
+            release.join(format!("server{}", env::consts::EXE_SUFFIX)),
+            release.join(format!("cli-tool{}", env::consts::EXE_SUFFIX)),
+        ]
+    );
+}
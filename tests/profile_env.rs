@@ -0,0 +1,42 @@
+//! `CARGO_PACK_PROFILE`, in its own test binary as the environment is shared by the tests
+
+extern crate cargo;
+extern crate cargo_pack;
+
+use cargo::util::Config;
+use cargo_pack::{CargoPack, default_profile};
+use std::env;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process;
+
+#[test]
+fn cargo_pack_profile_is_the_default_profile() {
+    let target_dir = env::temp_dir().join(format!("cargo-pack-profile-env-{}", process::id()));
+    let debug = target_dir.join("debug");
+    fs::create_dir_all(&debug).unwrap();
+    for name in &["server", "cli-tool"] {
+        File::create(debug.join(format!("{}{}", name, env::consts::EXE_SUFFIX))).unwrap();
+    }
+    let config = Config::default().unwrap();
+    let pack = CargoPack::builder(&config)
+        .with_manifest_path(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/custom-bins/Cargo.toml"))
+        .with_target_dir(target_dir.clone())
+        .build()
+        .unwrap();
+
+    env::remove_var("CARGO_PACK_PROFILE");
+    assert_eq!(default_profile(), "release");
+    let release = pack.binaries(None);
+
+    env::set_var("CARGO_PACK_PROFILE", "dev");
+    assert_eq!(default_profile(), "dev");
+    let dev = pack.binaries(None);
+    let explicit = pack.binaries("release");
+    env::remove_var("CARGO_PACK_PROFILE");
+    fs::remove_dir_all(&target_dir).unwrap();
+
+    assert!(release.is_err());
+    assert_eq!(dev.unwrap().len(), 2);
+    assert!(explicit.is_err());
+}